@@ -0,0 +1,170 @@
+//! Peak memory of the MaxMind GeoIp build.
+//!
+//! Generates a synthetic GeoLite2 database and builds the dataset twice, each time in a new
+//! process so the peak resident memory (VmHWM, Linux only) of one build does not hide the other:
+//! - `streaming`: the merge-join of `process_maxmind_geo_lite2_csv`
+//! - `staged`: the blocks loaded in a `HashMap` keyed by network before the dataset is built
+//!
+//! ```sh
+//! cargo run --release --example geoip_memory -- 2000000
+//! ```
+
+#[cfg(not(feature = "slow_geoip"))]
+mod bench {
+    use std::{collections::HashMap, io::Write, path::Path};
+
+    use usiem::{
+        prelude::{
+            geo_ip::{GeoIpDataset, GeoIpInfo},
+            SiemIp,
+        },
+        utilities::types::LogString,
+    };
+    use usiem_utils::{
+        csv::{CsvReader, CsvRecord},
+        maxmind::process_maxmind_geo_lite2_csv,
+        network::PrefixMode,
+    };
+
+    pub fn generate(dir: &Path, blocks: u32) -> std::io::Result<()> {
+        std::fs::create_dir_all(dir)?;
+        std::fs::write(
+            dir.join("GeoLite2-Country-Locations-en.csv"),
+            "geoname_id,locale_code,continent_code,continent_name,country_iso_code,country_name,is_in_european_union\n2510769,en,EU,Europe,ES,Spain,1\n",
+        )?;
+        let mut cities = String::from("geoname_id,locale_code,continent_code,continent_name,country_iso_code,country_name,subdivision_1_iso_code,subdivision_1_name,subdivision_2_iso_code,subdivision_2_name,city_name,metro_code,time_zone,is_in_european_union\n");
+        for id in 0..1000 {
+            cities.push_str(&format!(
+                "{},en,EU,Europe,ES,Spain,,,,,City {},,Europe/Madrid,1\n",
+                id, id
+            ));
+        }
+        std::fs::write(dir.join("GeoLite2-City-Locations-en.csv"), cities)?;
+        let mut city = std::io::BufWriter::new(std::fs::File::create(
+            dir.join("GeoLite2-City-Blocks-IPv4.csv"),
+        )?);
+        let mut asn = std::io::BufWriter::new(std::fs::File::create(
+            dir.join("GeoLite2-ASN-Blocks-IPv4.csv"),
+        )?);
+        writeln!(city, "network,geoname_id,registered_country_geoname_id,represented_country_geoname_id,is_anonymous_proxy,is_satellite_provider,postal_code,latitude,longitude,accuracy_radius")?;
+        writeln!(
+            asn,
+            "network,autonomous_system_number,autonomous_system_organization"
+        )?;
+        for block in 0..blocks {
+            let network = format!("{}/24", SiemIp::V4(0x0100_0000 + (block << 8)));
+            writeln!(
+                city,
+                "{},{},2510769,,0,0,28001,40.4165,-3.7026,100",
+                network,
+                block % 1000
+            )?;
+            writeln!(asn, "{},{},\"Provider {}, S.A.\"", network, block, block)?;
+        }
+        city.flush()?;
+        asn.flush()?;
+        for name in [
+            "GeoLite2-City-Blocks-IPv6.csv",
+            "GeoLite2-ASN-Blocks-IPv6.csv",
+        ] {
+            std::fs::write(dir.join(name), "network\n")?;
+        }
+        Ok(())
+    }
+
+    pub async fn streaming(dir: &Path) -> std::io::Result<GeoIpDataset> {
+        process_maxmind_geo_lite2_csv(dir, true, "en", PrefixMode::Lenient)
+            .await
+            .map(|(dataset, _)| dataset)
+            .map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidData, err))
+    }
+
+    /// The build before the merge-join: every block is kept in memory keyed by its network
+    pub async fn staged(dir: &Path) -> std::io::Result<GeoIpDataset> {
+        let mut networks: HashMap<String, GeoIpInfo> = HashMap::new();
+        let mut record = CsvRecord::new();
+        let file = tokio::fs::File::open(dir.join("GeoLite2-City-Blocks-IPv4.csv")).await?;
+        let mut reader = CsvReader::new(tokio::io::BufReader::new(file));
+        reader.read_header().await?;
+        while reader.read_record(&mut record).await? {
+            let info = GeoIpInfo {
+                city: LogString::Owned(format!("City {}", &record[1])),
+                country_iso: LogString::Borrowed("ES"),
+                latitude: record[7].parse().unwrap_or_default(),
+                longitude: record[8].parse().unwrap_or_default(),
+                ..Default::default()
+            };
+            networks.insert(record[0].to_string(), info);
+        }
+        let file = tokio::fs::File::open(dir.join("GeoLite2-ASN-Blocks-IPv4.csv")).await?;
+        let mut reader = CsvReader::new(tokio::io::BufReader::new(file));
+        reader.read_header().await?;
+        while reader.read_record(&mut record).await? {
+            if let Some(info) = networks.get_mut(&record[0]) {
+                info.asn = record[1].parse().unwrap_or_default();
+                info.isp = LogString::Owned(record[2].to_string());
+            }
+        }
+        let mut dataset = GeoIpDataset::new();
+        for (network, info) in networks {
+            if let Some((ip, net)) = network.split_once('/') {
+                if let (Ok(ip), Ok(net)) = (SiemIp::from_ip_str(ip), net.parse()) {
+                    dataset.insert(ip, net, info);
+                }
+            }
+        }
+        Ok(dataset)
+    }
+}
+
+/// Peak resident memory of the process in KiB
+fn peak_memory_kib() -> Option<u64> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    let line = status.lines().find(|v| v.starts_with("VmHWM:"))?;
+    line.split_whitespace().nth(1)?.parse().ok()
+}
+
+#[cfg(not(feature = "slow_geoip"))]
+#[tokio::main(flavor = "current_thread")]
+async fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    let dir = std::env::temp_dir().join("usiem_geoip_memory_bench");
+    match args.get(1).map(|v| &v[..]) {
+        Some(mode @ ("streaming" | "staged")) => {
+            let start = std::time::Instant::now();
+            let dataset = match mode {
+                "streaming" => bench::streaming(&dir).await,
+                _ => bench::staged(&dir).await,
+            }
+            .expect("the synthetic database must be valid");
+            let (networks4, _) = dataset.internal_ref();
+            println!(
+                "{:>9}: {} KiB peak, {:.2}s, {} networks",
+                mode,
+                peak_memory_kib().unwrap_or_default(),
+                start.elapsed().as_secs_f32(),
+                networks4.values().map(|v| v.len()).sum::<usize>()
+            );
+        }
+        blocks => {
+            let blocks = blocks.and_then(|v| v.parse().ok()).unwrap_or(1_000_000);
+            bench::generate(&dir, blocks).expect("cannot write the synthetic database");
+            println!("{} blocks", blocks);
+            let executable = std::env::current_exe().expect("cannot find the benchmark");
+            for mode in ["streaming", "staged"] {
+                let status = std::process::Command::new(&executable)
+                    .arg(mode)
+                    .status()
+                    .expect("cannot run the benchmark");
+                assert!(status.success());
+            }
+            let _ = std::fs::remove_dir_all(&dir);
+        }
+    }
+}
+
+#[cfg(feature = "slow_geoip")]
+fn main() {
+    let _ = peak_memory_kib;
+    eprintln!("The benchmark needs the in-memory GeoIp dataset, build it without slow_geoip");
+}
@@ -0,0 +1,26 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use usiem_utils::csv::{CsvHeader, CsvReader, CsvRecord};
+
+fuzz_target!(|data: &[u8]| {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .build()
+        .unwrap();
+    runtime.block_on(async {
+        let mut reader = CsvReader::new(data);
+        let mut record = CsvRecord::new();
+        let mut line = 0;
+        // Invalid UTF-8 ends the reading with an error
+        while let Ok(true) = reader.read_record(&mut record).await {
+            assert!(reader.line() > line);
+            line = reader.line();
+            let fields: Vec<&str> = record.iter().collect();
+            assert_eq!(record.len(), fields.len());
+            let header = CsvHeader::from_record(&record);
+            for name in header.columns() {
+                assert!(header.index(name).is_some());
+            }
+        }
+    });
+});
@@ -0,0 +1,25 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use usiem::prelude::SiemIp;
+use usiem_utils::network::{parse_network, PrefixMode};
+
+fuzz_target!(|data: &[u8]| {
+    let text = match std::str::from_utf8(data) {
+        Ok(v) => v,
+        Err(_) => return,
+    };
+    let lenient = parse_network(text, PrefixMode::Lenient);
+    if let Ok(strict) = parse_network(text, PrefixMode::Strict) {
+        assert_eq!(Ok(strict), lenient);
+    }
+    if let Ok((ip, net)) = lenient {
+        match ip {
+            SiemIp::V4(_) => assert!(net <= 32),
+            SiemIp::V6(_) => assert!(net <= 128),
+        }
+        // The networks are returned without host bits
+        let formatted = format!("{}/{}", ip, net);
+        assert_eq!(Ok((ip, net)), parse_network(&formatted, PrefixMode::Strict));
+    }
+});
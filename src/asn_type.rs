@@ -0,0 +1,152 @@
+use std::path::Path;
+
+use usiem::prelude::text_map::TextMapDataset;
+
+use crate::{
+    csv::{CsvReader, CsvRecord},
+    err::{ErrContext, TempErr, TempResult},
+};
+
+/// Name of the CustomMapText dataset that maps an ASN number to its type (hosting, vpn, isp, mobile or education)
+pub const ASN_TYPE_DATASET: &str = "AsnType";
+
+/// Normalizes the category names used by the public ASN lists into one of the supported types
+pub fn normalize_asn_type(asn_type: &str) -> Option<&'static str> {
+    match &asn_type.trim().to_lowercase()[..] {
+        "hosting" | "datacenter" | "data center" | "cloud" | "cdn" => Some("hosting"),
+        "vpn" | "proxy" | "tor" => Some("vpn"),
+        "isp" | "residential" | "broadband" => Some("isp"),
+        "mobile" | "cellular" => Some("mobile"),
+        "education" | "edu" | "university" => Some("education"),
+        _ => None,
+    }
+}
+
+/// Parses an ASN number with or without the `AS` prefix
+pub fn parse_asn(asn: &str) -> Option<u32> {
+    let asn = asn.trim();
+    let asn = match asn.get(..2) {
+        Some(prefix) if prefix.eq_ignore_ascii_case("as") => &asn[2..],
+        _ => asn,
+    };
+    asn.parse().ok()
+}
+
+/// Parses the list of files of the `ASN_TYPE_FILES` configuration: comma separated paths, optionally prefixed
+/// with the type assigned to all the ASNs in the file (`hosting=/lists/datacenter.csv`).
+pub fn parse_asn_type_sources(sources: &str) -> Vec<(Option<&str>, &str)> {
+    sources
+        .split(',')
+        .map(|v| v.trim())
+        .filter(|v| !v.is_empty())
+        .map(|source| match source.split_once('=') {
+            Some((asn_type, path)) => (Some(asn_type.trim()), path.trim()),
+            None => (None, source),
+        })
+        .collect()
+}
+
+/// Reads a CSV file with ASNs. The ASN must be in a column named `asn` (or in the first column).
+/// When `default_type` is None the type is read from a `type` or `category` column.
+/// The header is optional if the ASN is in the first column and the type in the second one.
+pub async fn read_asn_type_file<P: AsRef<Path>>(
+    path: P,
+    default_type: Option<&str>,
+) -> TempResult<Vec<(u32, &'static str)>> {
+    let default_type = match default_type {
+        Some(v) => Some(
+            normalize_asn_type(v)
+                .ok_or_else(|| TempErr::invalid(format!("unknown ASN type {}", v)))?,
+        ),
+        None => None,
+    };
+    let path = path.as_ref();
+    let file = tokio::fs::File::open(path).await.with_file(path)?;
+    let mut reader = CsvReader::new(tokio::io::BufReader::new(file));
+    let mut values = CsvRecord::new();
+    let mut asn_column = 0;
+    let mut type_column = Some(1);
+    let mut entries = Vec::with_capacity(4096);
+    let mut first_line = true;
+    while reader.read_record(&mut values).await.with_file(path)? {
+        if (values.len() == 1 && values[0].trim().is_empty()) || values[0].starts_with('#') {
+            continue;
+        }
+        if first_line {
+            first_line = false;
+            if values.get(0).and_then(parse_asn).is_none() {
+                type_column = None;
+                for (pos, name) in values.iter().enumerate() {
+                    match &name.trim().to_lowercase()[..] {
+                        "asn" | "as_number" | "autonomous_system_number" => asn_column = pos,
+                        "type" | "category" | "class" => type_column = Some(pos),
+                        _ => {}
+                    }
+                }
+                continue;
+            }
+        }
+        let asn = match values.get(asn_column).and_then(parse_asn) {
+            Some(v) => v,
+            None => continue,
+        };
+        let asn_type = match default_type {
+            Some(v) => Some(v),
+            None => type_column
+                .and_then(|pos| values.get(pos))
+                .and_then(|v| normalize_asn_type(v)),
+        };
+        if let Some(asn_type) = asn_type {
+            entries.push((asn, asn_type));
+        }
+    }
+    Ok(entries)
+}
+
+/// Builds the AsnType dataset. Later entries override the type of previous ones.
+pub fn build_asn_type_dataset(entries: &[(u32, &'static str)]) -> TextMapDataset {
+    let mut dataset = TextMapDataset::new();
+    for (asn, asn_type) in entries {
+        dataset.insert(asn.to_string(), asn_type.to_string());
+    }
+    dataset
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_dir::TestDir;
+
+    #[tokio::test]
+    async fn should_read_asn_lists() {
+        let dir = TestDir::new("asn_type");
+        let classified = dir.join("asn.csv");
+        tokio::fs::write(
+            &classified,
+            "ASN,Entity,Category\nAS16509,\"Amazon.com, Inc.\",Datacenter\n\"3352\",Telefonica,ISP\n9009,M247,Unknown\n",
+        )
+        .await
+        .unwrap();
+        let vpn = dir.join("vpn.csv");
+        tokio::fs::write(&vpn, "9009,M247\n# comment\nAS60068,Datacamp\n")
+            .await
+            .unwrap();
+        let sources = format!("{}, vpn={}", classified.display(), vpn.display());
+        let mut entries = Vec::new();
+        for (asn_type, path) in parse_asn_type_sources(&sources) {
+            entries.extend(read_asn_type_file(path, asn_type).await.unwrap());
+        }
+        assert_eq!(
+            vec![
+                (16509, "hosting"),
+                (3352, "isp"),
+                (9009, "vpn"),
+                (60068, "vpn")
+            ],
+            entries
+        );
+        let dataset = build_asn_type_dataset(&entries);
+        assert_eq!("hosting", &dataset.get("16509").unwrap()[..]);
+        assert!(read_asn_type_file(&vpn, Some("unknown")).await.is_err());
+    }
+}
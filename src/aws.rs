@@ -1,15 +1,27 @@
 use serde::{Deserialize, Serialize};
 use usiem::utilities::types::LogString;
 
-use crate::err::TempResult;
+use crate::{
+    err::TempResult,
+    fetcher::{FeedFetcher, FetchOrigin},
+};
 
-pub async fn get_aws_ips() -> TempResult<AwsIpRanges> {
-    let body = reqwest::get("https://ip-ranges.amazonaws.com/ip-ranges.json")
-    .await?
-    .text()
-    .await?;
-    let res: AwsIpRanges = usiem::serde_json::from_str(&body)?;
-    Ok(res)
+/// Configuration key to download the AWS IP ranges from another URL
+pub const AWS_IP_RANGES_URL: &str = "AWS_IP_RANGES_URL";
+pub const DEFAULT_AWS_IP_RANGES_URL: &str = "https://ip-ranges.amazonaws.com/ip-ranges.json";
+
+/// Downloads the AWS IP ranges, returning also how the feed was obtained
+pub async fn get_aws_ips<T: FeedFetcher>(
+    fetcher: &T,
+    url: &str,
+) -> TempResult<(AwsIpRanges, FetchOrigin)> {
+    let file = fetcher
+        .fetch_validated("aws-ip-ranges.json", url, |content| {
+            Ok(usiem::serde_json::from_slice::<AwsIpRanges>(content).map(|_| ())?)
+        })
+        .await?;
+    let res: AwsIpRanges = usiem::serde_json::from_slice(&file.read().await?)?;
+    Ok((res, file.origin))
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
@@ -101,5 +113,18 @@ pub fn static_region(region: String) -> LogString {
 
 #[tokio::test]
 async fn test_aws() {
-    let _res = get_aws_ips().await;
+    let fetcher =
+        crate::fetcher::FileFetcher::new(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures"));
+    let (res, origin) = get_aws_ips(&fetcher, DEFAULT_AWS_IP_RANGES_URL)
+        .await
+        .unwrap();
+    assert_eq!(FetchOrigin::Local, origin);
+    assert_eq!("1714560000", res.sync_token);
+    assert_eq!(4, res.prefixes.len());
+    assert_eq!("18.208.0.0/13", res.prefixes[3].ip_prefix);
+    assert_eq!("2600:1f18::/33", res.ipv6_prefixes[0].ipv6_prefix);
+    assert_eq!(
+        "AWS-us-east-1",
+        &static_region(res.prefixes[3].region.clone())[..]
+    );
 }
@@ -1,15 +1,27 @@
 use serde::{Deserialize, Serialize};
 use usiem::utilities::types::LogString;
 
-use crate::err::TempResult;
+use crate::{
+    err::TempResult,
+    fetcher::{FeedFetcher, FetchOrigin},
+};
 
-pub async fn get_azure_ips() -> TempResult<AzureIpRanges> {
-    let body = reqwest::get("https://download.microsoft.com/download/7/1/D/71D86715-5596-4529-9B13-DA13A5DE5B63/ServiceTags_Public_20230206.json")
-    .await?
-    .text()
-    .await?;
-    let res: AzureIpRanges = usiem::serde_json::from_str(&body)?;
-    Ok(res)
+/// Configuration key to download the Azure service tags from another URL
+pub const AZURE_SERVICE_TAGS_URL: &str = "AZURE_SERVICE_TAGS_URL";
+pub const DEFAULT_AZURE_SERVICE_TAGS_URL: &str = "https://download.microsoft.com/download/7/1/D/71D86715-5596-4529-9B13-DA13A5DE5B63/ServiceTags_Public_20230206.json";
+
+/// Downloads the Azure service tags, returning also how the feed was obtained
+pub async fn get_azure_ips<T: FeedFetcher>(
+    fetcher: &T,
+    url: &str,
+) -> TempResult<(AzureIpRanges, FetchOrigin)> {
+    let file = fetcher
+        .fetch_validated("azure-service-tags.json", url, |content| {
+            Ok(usiem::serde_json::from_slice::<AzureIpRanges>(content).map(|_| ())?)
+        })
+        .await?;
+    let res: AzureIpRanges = usiem::serde_json::from_slice(&file.read().await?)?;
+    Ok((res, file.origin))
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
@@ -194,5 +206,18 @@ pub fn static_service(service: &str) -> LogString {
 
 #[tokio::test]
 async fn test_azure() {
-    let _res = get_azure_ips().await;
+    let fetcher =
+        crate::fetcher::FileFetcher::new(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures"));
+    let (res, origin) = get_azure_ips(&fetcher, DEFAULT_AZURE_SERVICE_TAGS_URL)
+        .await
+        .unwrap();
+    assert_eq!(FetchOrigin::Local, origin);
+    assert_eq!(250, res.change_number);
+    assert_eq!(2, res.values.len());
+    assert_eq!("AzureStorage", res.values[1].properties.system_service);
+    assert_eq!(3, res.values[1].properties.address_prefixes.len());
+    assert_eq!(
+        "Azure-westeurope",
+        &static_region(&res.values[0].properties.region)[..]
+    );
 }
@@ -1,16 +1,165 @@
-use usiem::utilities::ip_utils::{ipv4_from_str, ipv6_from_str};
-
-pub(crate) fn parse_ip4_network(ip_net: &str) -> Option<(u32, u8)> {
-    let pos = ip_net.find('/')?;
-    let ip = &ip_net[..pos];
-    let net = ip_net[pos + 1..].parse::<u8>().ok()?;
-    let ip = ipv4_from_str(ip).ok()?;
-    Some((ip, net))
-}
-pub(crate) fn parse_ip6_network(ip_net: &str) -> Option<(u128, u8)> {
-    let pos = ip_net.find('/')?;
-    let ip = &ip_net[..pos];
-    let net = ip_net[pos + 1..].parse::<u8>().ok()?;
-    let ip = ipv6_from_str(ip).ok()?;
-    Some((ip, net))
+use usiem::prelude::SiemIp;
+
+/// Checks if the IP belongs to the network `net_ip/net`
+pub(crate) fn ip_in_network(ip: &SiemIp, net_ip: &SiemIp, net: u8) -> bool {
+    match (ip, net_ip) {
+        (SiemIp::V4(ip), SiemIp::V4(net_ip)) => {
+            let mask = u32::MAX.checked_shl(32 - net.min(32) as u32).unwrap_or(0);
+            ip & mask == net_ip & mask
+        }
+        (SiemIp::V6(ip), SiemIp::V6(net_ip)) => {
+            let mask = u128::MAX
+                .checked_shl(128 - net.min(128) as u32)
+                .unwrap_or(0);
+            ip & mask == net_ip & mask
+        }
+        _ => false,
+    }
+}
+
+/// Splits the inclusive range `start..=end` into the minimal list of CIDR blocks.
+/// `bits` is 32 for IPv4 ranges and 128 for IPv6 ranges.
+pub(crate) fn range_to_cidrs(start: u128, end: u128, bits: u32) -> Vec<(u128, u8)> {
+    let mut cidrs = Vec::new();
+    if start > end {
+        return cidrs;
+    }
+    let mut current = start;
+    loop {
+        let max_by_alignment = if current == 0 {
+            bits
+        } else {
+            current.trailing_zeros().min(bits)
+        };
+        let remaining = end - current;
+        let max_by_size = if remaining == u128::MAX {
+            128
+        } else {
+            127 - (remaining + 1).leading_zeros()
+        };
+        let host_bits = max_by_alignment.min(max_by_size).min(bits);
+        cidrs.push((current, (bits - host_bits) as u8));
+        let last = current + u128::MAX.checked_shr(128 - host_bits).unwrap_or(0);
+        if last >= end {
+            return cidrs;
+        }
+        current = last + 1;
+    }
+}
+
+/// First and last address of a network. `bits` is 32 for IPv4 and 128 for IPv6.
+pub(crate) fn network_range(ip: u128, net: u8, bits: u32) -> (u128, u128) {
+    let host_bits = bits - (net as u32).min(bits);
+    let host_mask = u128::MAX.checked_shr(128 - host_bits).unwrap_or(0);
+    (ip & !host_mask, (ip & !host_mask) | host_mask)
+}
+
+/// Splits the inclusive range `start..=end` into networks. IPv4-mapped IPv6 ranges (`::ffff:0:0/96`) are converted to IPv4.
+/// Returns an empty list if the range mixes IPv4 and IPv6 addresses.
+pub(crate) fn ip_range_to_networks(start: &SiemIp, end: &SiemIp) -> Vec<(SiemIp, u8)> {
+    match (start, end) {
+        (SiemIp::V4(start), SiemIp::V4(end)) => range_to_cidrs(*start as u128, *end as u128, 32)
+            .into_iter()
+            .map(|(ip, net)| (SiemIp::V4(ip as u32), net))
+            .collect(),
+        (SiemIp::V6(start), SiemIp::V6(end)) if start >> 32 == 0xffff && end >> 32 == 0xffff => {
+            ip_range_to_networks(&SiemIp::V4(*start as u32), &SiemIp::V4(*end as u32))
+        }
+        (SiemIp::V6(start), SiemIp::V6(end)) => range_to_cidrs(*start, *end, 128)
+            .into_iter()
+            .map(|(ip, net)| (SiemIp::V6(ip), net))
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+/// Current year and month in UTC
+pub(crate) fn current_year_month() -> (i64, u32) {
+    let seconds = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|v| v.as_secs())
+        .unwrap_or_default();
+    year_month_from_days((seconds / 86_400) as i64)
+}
+
+/// Converts days since 1970-01-01 into a (year, month) pair of the proleptic Gregorian calendar
+pub(crate) fn year_month_from_days(days: i64) -> (i64, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month)
+}
+
+/// Mean earth radius used by the great-circle calculations
+pub(crate) const EARTH_RADIUS_KM: f64 = 6371.0;
+
+/// Great-circle distance in kilometers between two coordinates in degrees
+pub(crate) fn haversine_distance(lat1: f64, lon1: f64, lat2: f64, lon2: f64) -> f64 {
+    let d_lat = (lat2 - lat1).to_radians();
+    let d_lon = (lon2 - lon1).to_radians();
+    let a = (d_lat / 2.0).sin().powi(2)
+        + lat1.to_radians().cos() * lat2.to_radians().cos() * (d_lon / 2.0).sin().powi(2);
+    2.0 * EARTH_RADIUS_KM * a.sqrt().min(1.0).asin()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_split_ranges_into_cidrs() {
+        assert_eq!(
+            vec![(0x0A000000, 8)],
+            range_to_cidrs(0x0A000000, 0x0AFFFFFF, 32)
+        );
+        assert_eq!(vec![(0, 0)], range_to_cidrs(0, u32::MAX as u128, 32));
+        assert_eq!(vec![(0, 0)], range_to_cidrs(0, u128::MAX, 128));
+        assert_eq!(vec![(1, 32), (2, 31), (4, 32)], range_to_cidrs(1, 4, 32));
+        assert_eq!((0x0A000000, 0x0AFFFFFF), network_range(0x0A010203, 8, 32));
+        assert_eq!((0, u128::MAX), network_range(5, 0, 128));
+    }
+
+    #[test]
+    fn should_calculate_great_circle_distances() {
+        // Madrid - New York
+        let distance = haversine_distance(40.4168, -3.7038, 40.7128, -74.006);
+        assert!((distance - 5768.0).abs() < 10.0, "{}", distance);
+        assert_eq!(0.0, haversine_distance(10.0, 20.0, 10.0, 20.0));
+        let antipodes = haversine_distance(0.0, 0.0, 0.0, 180.0);
+        assert!((antipodes - std::f64::consts::PI * EARTH_RADIUS_KM).abs() < 0.001);
+    }
+
+    #[test]
+    fn should_convert_ip_ranges_into_networks() {
+        assert_eq!(
+            vec![(SiemIp::V4(0x01000000), 24)],
+            ip_range_to_networks(&SiemIp::V4(0x01000000), &SiemIp::V4(0x010000FF))
+        );
+        assert_eq!(
+            vec![(SiemIp::V4(0x01000000), 24)],
+            ip_range_to_networks(&SiemIp::V6(0xffff_0100_0000), &SiemIp::V6(0xffff_0100_00ff))
+        );
+        assert_eq!(
+            vec![(SiemIp::V6(0x2001_0db8 << 96), 32)],
+            ip_range_to_networks(
+                &SiemIp::V6(0x2001_0db8 << 96),
+                &SiemIp::V6((0x2001_0db8 << 96) | (u128::MAX >> 32))
+            )
+        );
+        assert!(ip_range_to_networks(&SiemIp::V4(1), &SiemIp::V6(2)).is_empty());
+    }
+
+    #[test]
+    fn should_convert_days_into_dates() {
+        assert_eq!((1970, 1), year_month_from_days(0));
+        assert_eq!((2000, 2), year_month_from_days(11_016));
+        assert_eq!((2024, 2), year_month_from_days(19_782));
+        assert_eq!((2024, 3), year_month_from_days(19_783));
+    }
 }
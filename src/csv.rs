@@ -1,101 +1,349 @@
-pub struct CsvLexer {
-    input: Vec<char>,
-    pub position: usize,
-    pub read_position: usize,
-    pub ch: char,
+//! Streaming CSV reader following RFC 4180.
+//!
+//! Fields can be quoted, quotes inside quoted fields are doubled (`""`) and quoted fields can
+//! span several lines. Backslash has no special meaning. Like most readers, malformed input is
+//! accepted: a quote inside an unquoted field is kept and the text after a closing quote is
+//! appended to the field. Blank lines are skipped.
+
+use tokio::io::{AsyncBufRead, AsyncBufReadExt};
+
+/// Fields of a record, stored in a single buffer that is reused between records
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct CsvRecord {
+    buffer: String,
+    ends: Vec<usize>,
 }
-fn transform_escape_char(ch: char) -> Result<char, ()> {
-    match ch {
-        'n' => Ok('\n'),
-        't' => Ok('\t'),
-        'r' => Ok('\r'),
-        '0' => Ok('\0'),
-        '*' => Err(()),
-        _ => Ok(ch),
+
+impl CsvRecord {
+    pub fn new() -> Self {
+        Self {
+            buffer: String::with_capacity(256),
+            ends: Vec::with_capacity(16),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.ends.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ends.is_empty()
+    }
+
+    pub fn get(&self, pos: usize) -> Option<&str> {
+        let end = *self.ends.get(pos)?;
+        let start = match pos {
+            0 => 0,
+            _ => self.ends[pos - 1],
+        };
+        Some(&self.buffer[start..end])
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &str> {
+        (0..self.len()).filter_map(|pos| self.get(pos))
+    }
+
+    pub fn clear(&mut self) {
+        self.buffer.clear();
+        self.ends.clear();
+    }
+
+    fn end_field(&mut self) {
+        self.ends.push(self.buffer.len());
     }
 }
 
-impl CsvLexer {
-    pub fn new(input: Vec<char>) -> Self {
+impl std::ops::Index<usize> for CsvRecord {
+    type Output = str;
+
+    fn index(&self, pos: usize) -> &str {
+        match self.get(pos) {
+            Some(v) => v,
+            None => panic!("field {} out of range, the record has {}", pos, self.len()),
+        }
+    }
+}
+
+/// Column names of a file, compared ignoring case and surrounding spaces
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct CsvHeader {
+    columns: Vec<String>,
+}
+
+impl CsvHeader {
+    pub fn from_record(record: &CsvRecord) -> Self {
         Self {
-            input: input,
-            position: 0,
-            read_position: 0,
-            ch: '0',
+            columns: record
+                .iter()
+                .map(|v| v.trim_start_matches('\u{feff}').trim().to_lowercase())
+                .collect(),
         }
     }
 
-    pub fn read_char(&mut self) {
-        if self.read_position >= self.input.len() {
-            self.ch = '0';
-        } else {
-            self.ch = self.input[self.read_position];
+    /// Position of a column
+    pub fn index(&self, name: &str) -> Option<usize> {
+        self.columns
+            .iter()
+            .position(|v| v.eq_ignore_ascii_case(name))
+    }
+
+    /// Position of a column that must exist
+    pub fn required(&self, name: &str) -> std::io::Result<usize> {
+        self.index(name).ok_or_else(|| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("the header has no {} column", name),
+            )
+        })
+    }
+
+    pub fn columns(&self) -> &[String] {
+        &self.columns
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum State {
+    StartField,
+    Unquoted,
+    Quoted,
+    /// A quote inside a quoted field: the end of the field or the first half of `""`
+    QuoteInQuoted,
+}
+
+pub struct CsvReader<R> {
+    reader: R,
+    line: String,
+    line_number: usize,
+    record_line: usize,
+}
+
+impl<R: AsyncBufRead + Unpin> CsvReader<R> {
+    pub fn new(reader: R) -> Self {
+        Self {
+            reader,
+            line: String::with_capacity(256),
+            line_number: 0,
+            record_line: 0,
         }
-        self.position = self.read_position;
-        self.read_position = self.read_position + 1;
     }
 
-    pub fn next_token(&mut self) -> Token {
-        let read_identifier = |l: &mut CsvLexer| -> Vec<char> {
-            let position = l.position;
-            while l.position < l.input.len() && l.ch != ','  {
-                l.read_char();
-            }
-            l.input[position..l.position].to_vec()
-        };
+    /// Line where the last record read starts. Lines start at 1.
+    pub fn line(&self) -> usize {
+        self.record_line
+    }
 
-        let read_string = |l: &mut CsvLexer| -> Vec<char> {
-            let mut is_escape = false;
-            let mut to_ret = Vec::with_capacity(32);
-            while l.position < l.input.len() && !(l.ch == '"' && !is_escape) {
-                if l.ch == '\\' {
-                    if is_escape {
-                        to_ret.push('\\');
-                    }
-                    is_escape = !is_escape;
-                } else {
-                    if is_escape {
-                        match transform_escape_char(l.ch) {
-                            Ok(ch) => to_ret.push(ch),
-                            Err(_) => {
-                                // The \\ character is used
-                                to_ret.push('\\');
-                                to_ret.push(l.ch);
-                            }
-                        };
-                    } else {
-                        to_ret.push(l.ch);
-                    }
-                    is_escape = false;
+    /// Reads the first record as the header. Returns None with an empty file.
+    pub async fn read_header(&mut self) -> std::io::Result<Option<CsvHeader>> {
+        let mut record = CsvRecord::new();
+        Ok(match self.read_record(&mut record).await? {
+            true => Some(CsvHeader::from_record(&record)),
+            false => None,
+        })
+    }
+
+    /// Reads the next record into `record`. Returns false at the end of the file.
+    pub async fn read_record(&mut self, record: &mut CsvRecord) -> std::io::Result<bool> {
+        record.clear();
+        let mut state = State::StartField;
+        loop {
+            self.line.clear();
+            if self.reader.read_line(&mut self.line).await? == 0 {
+                // A quoted field without the closing quote ends with the file
+                if state != State::StartField || !record.is_empty() {
+                    record.end_field();
+                    return Ok(true);
                 }
-                l.read_char();
+                return Ok(false);
             }
-            to_ret
-        };
-
-        let tok: Token;
-        match self.ch {
-            ',' => {
-                tok = Token::COMMA;
-            },
-            '"' => {
-                self.read_char();
-                let data = read_string(self);
-                tok = Token::String(data.iter().collect())
+            self.line_number += 1;
+            let body = self
+                .line
+                .strip_suffix('\n')
+                .map(|v| v.strip_suffix('\r').unwrap_or(v))
+                .unwrap_or(&self.line);
+            if state == State::StartField && record.is_empty() {
+                if body.is_empty() {
+                    continue;
+                }
+                self.record_line = self.line_number;
             }
-            _ => {
-                let data: Vec<char> = read_identifier(self);
-                tok = Token::String(data.iter().collect())
+            state = parse_line(state, body, record);
+            if state == State::Quoted {
+                // The line break belongs to the field
+                record.buffer.push_str(&self.line[body.len()..]);
+            } else {
+                record.end_field();
+                return Ok(true);
             }
         }
-        self.read_char();
-        tok
     }
 }
 
+fn parse_line(mut state: State, body: &str, record: &mut CsvRecord) -> State {
+    for ch in body.chars() {
+        state = match (state, ch) {
+            (State::StartField, '"') => State::Quoted,
+            (State::StartField | State::Unquoted | State::QuoteInQuoted, ',') => {
+                record.end_field();
+                State::StartField
+            }
+            (State::StartField | State::Unquoted, ch) => {
+                record.buffer.push(ch);
+                State::Unquoted
+            }
+            (State::Quoted, '"') => State::QuoteInQuoted,
+            (State::Quoted, ch) => {
+                record.buffer.push(ch);
+                State::Quoted
+            }
+            (State::QuoteInQuoted, '"') => {
+                record.buffer.push('"');
+                State::Quoted
+            }
+            (State::QuoteInQuoted, ch) => {
+                record.buffer.push(ch);
+                State::Unquoted
+            }
+        };
+    }
+    state
+}
+
+#[cfg(test)]
+mod tests {
+    use proptest::{collection::vec, prelude::*};
+
+    use super::*;
+
+    async fn read_all(input: &str, capacity: usize) -> Vec<Vec<String>> {
+        let reader = tokio::io::BufReader::with_capacity(capacity, input.as_bytes());
+        let mut reader = CsvReader::new(reader);
+        let mut record = CsvRecord::new();
+        let mut records = Vec::new();
+        while reader.read_record(&mut record).await.unwrap() {
+            records.push(record.iter().map(|v| v.to_string()).collect());
+        }
+        records
+    }
+
+    /// Records read by the csv crate, the reference implementation
+    fn read_reference(input: &str) -> Vec<Vec<String>> {
+        csv::ReaderBuilder::new()
+            .has_headers(false)
+            .flexible(true)
+            .from_reader(input.as_bytes())
+            .records()
+            .map(|record| record.unwrap().iter().map(|v| v.to_string()).collect())
+            .collect()
+    }
 
-#[derive(Debug, PartialEq)]
-pub enum Token {
-    COMMA,
-    String(String)
-}
\ No newline at end of file
+    fn write_reference(records: &[Vec<String>]) -> String {
+        let mut writer = csv::WriterBuilder::new()
+            .flexible(true)
+            .terminator(csv::Terminator::CRLF)
+            .from_writer(Vec::new());
+        for record in records {
+            writer.write_record(record).unwrap();
+        }
+        String::from_utf8(writer.into_inner().unwrap()).unwrap()
+    }
+
+    fn read_blocking(input: &str, capacity: usize) -> Vec<Vec<String>> {
+        tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap()
+            .block_on(read_all(input, capacity))
+    }
+
+    #[tokio::test]
+    async fn should_read_rfc4180_records() {
+        let input = concat!(
+            "network,asn,\"organization\"\n",
+            "1.9.0.0/16,4788,\"TM Net, Internet Service Provider\"\n",
+            "\r\n",
+            "2.0.0.0/8,,\"Say \"\"hello\"\"\"\r\n",
+            "3.0.0.0/8,1,\"Multi\nline\"\n",
+            "4.0.0.0/8,2,C:\\path\\\n",
+            "5.0.0.0/8,3,\"\"",
+        );
+        let records = read_all(input, 8 * 1024).await;
+        assert_eq!(
+            vec![
+                vec!["network", "asn", "organization"],
+                vec!["1.9.0.0/16", "4788", "TM Net, Internet Service Provider"],
+                vec!["2.0.0.0/8", "", "Say \"hello\""],
+                vec!["3.0.0.0/8", "1", "Multi\nline"],
+                vec!["4.0.0.0/8", "2", "C:\\path\\"],
+                vec!["5.0.0.0/8", "3", ""],
+            ],
+            records
+        );
+
+        let reader = tokio::io::BufReader::new(input.as_bytes());
+        let mut reader = CsvReader::new(reader);
+        let header = reader.read_header().await.unwrap().unwrap();
+        assert_eq!(Some(2), header.index("Organization"));
+        assert_eq!(None, header.index("city"));
+        assert!(header.required("city").is_err());
+        let mut record = CsvRecord::new();
+        let mut lines = Vec::new();
+        while reader.read_record(&mut record).await.unwrap() {
+            lines.push(reader.line());
+        }
+        assert_eq!(vec![2, 4, 5, 7, 8], lines);
+    }
+
+    #[tokio::test]
+    async fn should_accept_malformed_quotes() {
+        let records = read_all("a\"b,\"c\"d,\"e\n", 8 * 1024).await;
+        assert_eq!(vec![vec!["a\"b", "cd", "e\n"]], records);
+    }
+
+    const BYTES: [u8; 10] = [b'a', b',', b'"', b'\r', b'\n', b' ', 0, 0xc3, 0xb1, 0xff];
+
+    proptest! {
+        #[test]
+        fn should_match_reference_writer(
+            records in vec(vec("[ab ,\"\r\n\\\\ñ]{0,6}", 1..5), 1..4),
+            capacity in 1..16usize,
+        ) {
+            let input = write_reference(&records);
+            // Small buffers split the records between reads
+            prop_assert_eq!(&records, &read_blocking(&input, capacity));
+            prop_assert_eq!(read_reference(&input), read_blocking(&input, capacity));
+        }
+
+        #[test]
+        fn should_not_panic_with_random_input(
+            input in vec(proptest::sample::select(&BYTES[..]), 0..40),
+            capacity in 1..8usize,
+        ) {
+            let lines = tokio::runtime::Builder::new_current_thread()
+                .build()
+                .unwrap()
+                .block_on(async {
+                    let reader = tokio::io::BufReader::with_capacity(capacity, &input[..]);
+                    let mut reader = CsvReader::new(reader);
+                    let mut record = CsvRecord::new();
+                    let mut lines = Vec::new();
+                    // Invalid UTF-8 ends the reading with an error
+                    while let Ok(true) = reader.read_record(&mut record).await {
+                        assert_eq!(record.len(), record.iter().count());
+                        lines.push(reader.line());
+                    }
+                    lines
+                });
+            prop_assert!(lines.windows(2).all(|v| v[0] < v[1]), "{:?}", lines);
+            prop_assert!(lines.first().map(|v| *v > 0).unwrap_or(true));
+        }
+
+        #[test]
+        fn should_match_reference_reader(
+            input in "([ab ,\"\n]|\r\n){0,40}",
+            capacity in 1..16usize,
+        ) {
+            prop_assert_eq!(read_reference(&input), read_blocking(&input, capacity));
+        }
+    }
+}
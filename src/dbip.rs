@@ -0,0 +1,200 @@
+use std::path::{Path, PathBuf};
+
+use usiem::{
+    prelude::{
+        geo_ip::{GeoIpDataset, GeoIpInfo},
+        SiemIp,
+    },
+    utilities::types::LogString,
+};
+
+use crate::{
+    common::{current_year_month, ip_range_to_networks},
+    csv::{CsvReader, CsvRecord},
+    err::{ErrContext, TempErr, TempResult},
+    fetcher::FeedFetcher,
+    maxmind::get_static_country_iso_name,
+    network::{parse_ip_range, InvalidNetwork, LoadedEntries, PrefixMode},
+};
+
+/// Configuration key with the base URL of the DB-IP files or of a mirror with the same file names
+pub const DBIP_BASE_URL: &str = "DBIP_BASE_URL";
+pub const DEFAULT_DBIP_BASE_URL: &str = "https://download.db-ip.com/free";
+
+/// Downloads the DB-IP IP to City Lite database of the current month.
+/// The file of the new month is published during the first days, so the previous one is used as a fallback.
+pub async fn download_dbip_city_lite<T: FeedFetcher>(
+    fetcher: &T,
+    base_url: &str,
+) -> TempResult<PathBuf> {
+    let (year, month) = current_year_month();
+    let (previous_year, previous_month) = if month == 1 {
+        (year - 1, 12)
+    } else {
+        (year, month - 1)
+    };
+    match download_dbip_city_lite_edition(fetcher, base_url, year, month).await {
+        Ok(v) => Ok(v),
+        Err(_) => {
+            download_dbip_city_lite_edition(fetcher, base_url, previous_year, previous_month).await
+        }
+    }
+}
+
+pub async fn download_dbip_city_lite_edition<T: FeedFetcher>(
+    fetcher: &T,
+    base_url: &str,
+    year: i64,
+    month: u32,
+) -> TempResult<PathBuf> {
+    let file_name = format!("dbip-city-lite-{}-{:02}.csv.gz", year, month);
+    let database_url = format!("{}/{}", base_url.trim_end_matches('/'), file_name);
+    let file = fetcher.fetch(&file_name, &database_url).await?;
+    Ok(file.path)
+}
+
+/// Decompresses a gzip file next to the original one, removing the `.gz` extension
+pub async fn extract_gz_db(path: &Path) -> TempResult<PathBuf> {
+    let path = path.to_path_buf();
+    match tokio::task::spawn_blocking(move || {
+        let extracted = path.with_extension("");
+        if extracted == path {
+            return Err(TempErr::file(&path, TempErr::invalid("not a .gz file")));
+        }
+        let file = std::fs::File::open(&path).with_file(&path)?;
+        let mut decoder = flate2::read::GzDecoder::new(std::io::BufReader::new(file));
+        let mut output = std::fs::File::create(&extracted).with_file(&extracted)?;
+        std::io::copy(&mut decoder, &mut output).with_file(&path)?;
+        Ok(extracted)
+    })
+    .await
+    {
+        Ok(v) => v,
+        Err(_) => Err(TempErr::invalid("the gzip extraction task was aborted")),
+    }
+}
+
+/// Inserts the ranges of a DB-IP City Lite CSV into the GeoIp dataset. Returns the number of ranges processed and
+/// of rows rejected: short rows, invalid addresses and the ranges rejected by `mode`.
+///
+/// The file has no header and the columns are `ip_start,ip_end,continent,country,stateprov,city,latitude,longitude`.
+/// DB-IP only provides the ISO code of the country.
+pub async fn process_dbip_city_lite_csv<P: AsRef<Path>>(
+    path: P,
+    mode: PrefixMode,
+    dataset: &mut GeoIpDataset,
+) -> TempResult<LoadedEntries> {
+    let path = path.as_ref();
+    let file = tokio::fs::File::open(path).await.with_file(path)?;
+    let mut reader = CsvReader::new(tokio::io::BufReader::new(file));
+    let mut values = CsvRecord::new();
+    let mut entries = LoadedEntries::default();
+    while reader.read_record(&mut values).await.with_file(path)? {
+        if values.len() < 8 {
+            let row = values.iter().collect::<Vec<_>>().join(",");
+            entries.reject(path, reader.line(), InvalidNetwork::Range(row));
+            continue;
+        }
+        let (start, end) = match (
+            SiemIp::from_ip_str(&values[0]),
+            SiemIp::from_ip_str(&values[1]),
+        ) {
+            (Ok(start), Ok(end)) => (start, end),
+            _ => {
+                let range = format!("{}-{}", &values[0], &values[1]);
+                entries.reject(path, reader.line(), InvalidNetwork::Range(range));
+                continue;
+            }
+        };
+        // Reserved ranges use ZZ as the country
+        if &values[3] == "ZZ" || values[3].is_empty() {
+            continue;
+        }
+        let (start, end) = match parse_ip_range(start, end, mode) {
+            Ok(v) => v,
+            Err(reason) => {
+                entries.reject(path, reader.line(), reason);
+                continue;
+            }
+        };
+        let info = GeoIpInfo {
+            country_iso: get_static_country_iso_name(&values[3]),
+            city: LogString::Owned(values[5].to_string()),
+            latitude: values[6].parse().unwrap_or_default(),
+            longitude: values[7].parse().unwrap_or_default(),
+            ..Default::default()
+        };
+        for (ip, net) in ip_range_to_networks(&start, &end) {
+            dataset.insert(ip, net, info.clone());
+        }
+        entries.loaded += 1;
+    }
+    Ok(entries)
+}
+
+#[cfg(all(test, not(feature = "slow_geoip")))]
+mod tests {
+    use std::io::Write;
+
+    use super::*;
+    use crate::test_dir::TestDir;
+
+    #[tokio::test]
+    async fn should_process_city_lite_csv() {
+        let dir = TestDir::new("dbip");
+        let path = dir.join("dbip-city-lite.csv.gz");
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder
+            .write_all(
+                concat!(
+                    "0.0.0.0,0.255.255.255,ZZ,ZZ,,,0,0\n",
+                    "1.0.0.0,1.0.0.255,OC,AU,Queensland,\"South Brisbane\",-27.4767,153.017\n",
+                    "1.0.4.0,1.0.7.255,OC,AU,Victoria,Melbourne,-37.814,144.963\n",
+                    "2001:200::,2001:200:ffff:ffff:ffff:ffff:ffff:ffff,AS,JP,Tokyo,Tokyo,35.6895,139.692\n",
+                    // Rejected in both modes
+                    "1.0.8.0,2001:200::,AS,CN,,,0,0\n",
+                    "1.0.9.0,1.0.9.256,AS,CN,,,0,0\n",
+                    // Reversed
+                    "1.0.10.255,1.0.10.0,AS,CN,,Guangzhou,23.1167,113.25\n",
+                )
+                .as_bytes(),
+            )
+            .unwrap();
+        tokio::fs::write(&path, encoder.finish().unwrap())
+            .await
+            .unwrap();
+        let path = extract_gz_db(&path).await.unwrap();
+        assert_eq!(Some("csv"), path.extension().and_then(|v| v.to_str()));
+        let mut dataset = GeoIpDataset::new();
+        let entries = process_dbip_city_lite_csv(&path, PrefixMode::Lenient, &mut dataset)
+            .await
+            .unwrap();
+        assert_eq!((4, 2), (entries.loaded, entries.rejected));
+        let samples: Vec<String> = entries.samples.iter().map(|v| v.to_string()).collect();
+        let expected = vec![
+            format!("{}:5: invalid range 1.0.8.0-2001:200::", path.display()),
+            format!("{}:6: invalid range 1.0.9.0-1.0.9.256", path.display()),
+        ];
+        assert_eq!(expected, samples);
+        let get = |ip: &str| dataset.get(&SiemIp::from_ip_str(ip).unwrap()).cloned();
+        let info = get("1.0.0.1").unwrap();
+        assert_eq!("AU", &info.country_iso[..]);
+        assert_eq!("South Brisbane", &info.city[..]);
+        assert_eq!("Melbourne", &get("1.0.6.1").unwrap().city[..]);
+        assert_eq!("JP", &get("2001:200::1").unwrap().country_iso[..]);
+        assert!(get("0.1.2.3").is_none());
+        assert_eq!("Guangzhou", &get("1.0.10.1").unwrap().city[..]);
+        let mut dataset = GeoIpDataset::new();
+        let entries = process_dbip_city_lite_csv(&path, PrefixMode::Strict, &mut dataset)
+            .await
+            .unwrap();
+        assert_eq!((3, 3), (entries.loaded, entries.rejected));
+        let samples: Vec<String> = entries.samples.iter().map(|v| v.to_string()).collect();
+        let expected = vec![
+            format!("{}:5: invalid range 1.0.8.0-2001:200::", path.display()),
+            format!("{}:6: invalid range 1.0.9.0-1.0.9.256", path.display()),
+            format!("{}:7: invalid range 1.0.10.255-1.0.10.0", path.display()),
+        ];
+        assert_eq!(expected, samples);
+    }
+}
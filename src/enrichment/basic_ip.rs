@@ -8,12 +8,30 @@ use usiem::{
     utilities::types::LogString,
 };
 
-#[derive(Clone)]
-pub struct BasicIPEnricher {}
+use super::field_filter::{FieldFilter, ResolvedFieldFilter};
+
+/// Configuration key with the IP fields checked against the block list and the MAC dataset
+pub const BASIC_IP_ALLOW_FIELDS: &str = "BASIC_IP_ENRICHER_ALLOW_FIELDS";
+/// Configuration key with the IP fields ignored by the BasicIPEnricher
+pub const BASIC_IP_DENY_FIELDS: &str = "BASIC_IP_ENRICHER_DENY_FIELDS";
+
+#[derive(Clone, Default)]
+pub struct BasicIPEnricher {
+    fields: FieldFilter,
+}
+
+impl BasicIPEnricher {
+    pub const fn new(fields: FieldFilter) -> Self {
+        Self { fields }
+    }
+}
 
 impl LogEnrichment for BasicIPEnricher {
     fn enrich(&self, mut log: SiemLog, datasets: &DatasetHolder) -> SiemLog {
-        if let Some(fields) = enrich_block_ip(&mut log, datasets) {
+        let ip_fields = self
+            .fields
+            .resolve(datasets, BASIC_IP_ALLOW_FIELDS, BASIC_IP_DENY_FIELDS);
+        if let Some(fields) = enrich_block_ip(&mut log, datasets, &ip_fields) {
             for (name, value) in fields {
                 log.insert(name, value);
             }
@@ -23,7 +41,7 @@ impl LogEnrichment for BasicIPEnricher {
                 log.insert(name, value);
             }
         }
-        if let Some(fields) = enrich_mac_ip(&mut log, datasets) {
+        if let Some(fields) = enrich_mac_ip(&mut log, datasets, &ip_fields) {
             for (name, value) in fields {
                 log.insert(name, value);
             }
@@ -53,11 +71,15 @@ impl LogEnrichment for BasicIPEnricher {
 fn enrich_block_ip(
     log: &mut SiemLog,
     datasets: &DatasetHolder,
+    ip_fields: &ResolvedFieldFilter,
 ) -> Option<Vec<(LogString, SiemField)>> {
     let ip_info: &IpSetSynDataset = datasets.get(&SiemDatasetType::BlockIp)?.try_into().ok()?;
     let mut new_fields = Vec::with_capacity(32);
     let mut block_list = false;
     for (field_name, ip_field) in log.ip_fields() {
+        if !ip_fields.is_allowed(field_name) {
+            continue;
+        }
         let ip: &SiemIp = match ip_field.try_into() {
             Ok(v) => v,
             Err(_) => continue,
@@ -79,12 +101,16 @@ fn enrich_block_ip(
 fn enrich_mac_ip(
     log: &mut SiemLog,
     datasets: &DatasetHolder,
+    ip_fields: &ResolvedFieldFilter,
 ) -> Option<Vec<(LogString, SiemField)>> {
     let mac_info: &IpMapSynDataset = datasets.get(&SiemDatasetType::IpMac)?.try_into().ok()?;
     let host_info: &TextMapSynDataset = datasets.get(&SiemDatasetType::MacHost)?.try_into().ok()?;
     let mut new_fields = Vec::with_capacity(32);
 
     for (field_name, ip_field) in log.ip_fields() {
+        if !ip_fields.is_allowed(field_name) {
+            continue;
+        }
         let ip: &SiemIp = match ip_field.try_into() {
             Ok(v) => v,
             Err(_) => continue,
@@ -130,24 +156,20 @@ fn enrich_asset_with_tags(
         datasets.get(&SiemDatasetType::AssetTag)?.try_into().ok()?;
 
     let mut new_fields = Vec::with_capacity(32);
-    if let Some(source_host) = log.field("source.domain") {
-        if let SiemField::Text(hostname) = source_host {
-            if let Some(asset_tag) = assets_info.get(&hostname[..]) {
-                new_fields.push((
-                    LogString::Owned(format!("source.tags")),
-                    SiemField::Array(asset_tag.clone()),
-                ));
-            }
+    if let Some(SiemField::Text(hostname)) = log.field("source.domain") {
+        if let Some(asset_tag) = assets_info.get(&hostname[..]) {
+            new_fields.push((
+                LogString::Borrowed("source.tags"),
+                SiemField::Array(asset_tag.clone()),
+            ));
         }
     }
-    if let Some(source_host) = log.field("destination.domain") {
-        if let SiemField::Text(hostname) = source_host {
-            if let Some(asset_tag) = assets_info.get(&hostname[..]) {
-                new_fields.push((
-                    LogString::Owned(format!("destination.tags")),
-                    SiemField::Array(asset_tag.clone()),
-                ));
-            }
+    if let Some(SiemField::Text(hostname)) = log.field("destination.domain") {
+        if let Some(asset_tag) = assets_info.get(&hostname[..]) {
+            new_fields.push((
+                LogString::Borrowed("destination.tags"),
+                SiemField::Array(asset_tag.clone()),
+            ));
         }
     }
     Some(new_fields)
@@ -164,24 +186,20 @@ fn enrich_asset_with_vulnerabilities(
 
     let mut new_fields = Vec::with_capacity(32);
 
-    if let Some(source_host) = log.field("source.domain") {
-        if let SiemField::Text(hostname) = source_host {
-            if let Some(vulnerabilities) = assets_info.get(&hostname[..]) {
-                new_fields.push((
-                    LogString::Owned(format!("source.vulnerability.ids")),
-                    SiemField::Array(vulnerabilities.clone()),
-                ));
-            }
+    if let Some(SiemField::Text(hostname)) = log.field("source.domain") {
+        if let Some(vulnerabilities) = assets_info.get(&hostname[..]) {
+            new_fields.push((
+                LogString::Borrowed("source.vulnerability.ids"),
+                SiemField::Array(vulnerabilities.clone()),
+            ));
         }
     }
-    if let Some(source_host) = log.field("destination.domain") {
-        if let SiemField::Text(hostname) = source_host {
-            if let Some(vulnerabilities) = assets_info.get(&hostname[..]) {
-                new_fields.push((
-                    LogString::Owned(format!("destination.vulnerability.ids")),
-                    SiemField::Array(vulnerabilities.clone()),
-                ));
-            }
+    if let Some(SiemField::Text(hostname)) = log.field("destination.domain") {
+        if let Some(vulnerabilities) = assets_info.get(&hostname[..]) {
+            new_fields.push((
+                LogString::Borrowed("destination.vulnerability.ids"),
+                SiemField::Array(vulnerabilities.clone()),
+            ));
         }
     }
     Some(new_fields)
@@ -198,26 +216,22 @@ fn enrich_block_domain(
 
     let mut new_fields = Vec::with_capacity(32);
     let mut block_list = false;
-    if let Some(source_host) = log.field("source.domain") {
-        if let SiemField::Text(hostname) = source_host {
-            if block_domain.contains(hostname) {
-                new_fields.push((
-                    LogString::Owned(format!("source.tags")),
-                    SiemField::Array(vec![LogString::Borrowed(BLOCKED_IP)]),
-                ));
-                block_list = true;
-            }
+    if let Some(SiemField::Text(hostname)) = log.field("source.domain") {
+        if block_domain.contains(hostname) {
+            new_fields.push((
+                LogString::Borrowed("source.tags"),
+                SiemField::Array(vec![LogString::Borrowed(BLOCKED_IP)]),
+            ));
+            block_list = true;
         }
     }
-    if let Some(source_host) = log.field("destination.domain") {
-        if let SiemField::Text(hostname) = source_host {
-            if block_domain.contains(hostname) {
-                new_fields.push((
-                    LogString::Owned(format!("destination.tags")),
-                    SiemField::Array(vec![LogString::Borrowed(BLOCKED_IP)]),
-                ));
-                block_list = true;
-            }
+    if let Some(SiemField::Text(hostname)) = log.field("destination.domain") {
+        if block_domain.contains(hostname) {
+            new_fields.push((
+                LogString::Borrowed("destination.tags"),
+                SiemField::Array(vec![LogString::Borrowed(BLOCKED_IP)]),
+            ));
+            block_list = true;
         }
     }
     if block_list {
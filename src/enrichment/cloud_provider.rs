@@ -3,8 +3,23 @@ use usiem::{
     utilities::types::LogString,
 };
 
-#[derive(Clone)]
-pub struct CloudProviderEnricher {}
+use super::field_filter::FieldFilter;
+
+/// Configuration key with the IP fields to check against the IpCloudProvider dataset
+pub const CLOUD_PROVIDER_ALLOW_FIELDS: &str = "CLOUD_PROVIDER_ENRICHER_ALLOW_FIELDS";
+/// Configuration key with the IP fields ignored by the CloudProviderEnricher
+pub const CLOUD_PROVIDER_DENY_FIELDS: &str = "CLOUD_PROVIDER_ENRICHER_DENY_FIELDS";
+
+#[derive(Clone, Default)]
+pub struct CloudProviderEnricher {
+    fields: FieldFilter,
+}
+
+impl CloudProviderEnricher {
+    pub const fn new(fields: FieldFilter) -> Self {
+        Self { fields }
+    }
+}
 
 impl LogEnrichment for CloudProviderEnricher {
     fn enrich(
@@ -20,8 +35,16 @@ impl LogEnrichment for CloudProviderEnricher {
             },
             None => return log,
         };
+        let fields = self.fields.resolve(
+            datasets,
+            CLOUD_PROVIDER_ALLOW_FIELDS,
+            CLOUD_PROVIDER_DENY_FIELDS,
+        );
         let mut new_fields = Vec::with_capacity(32);
         for (field_name, ip_field) in log.ip_fields() {
+            if !fields.is_allowed(field_name) {
+                continue;
+            }
             let ip: &SiemIp = match ip_field.try_into() {
                 Ok(v) => v,
                 Err(_) => continue,
@@ -50,3 +73,50 @@ impl LogEnrichment for CloudProviderEnricher {
         "Adds cloud provider information like Google, Azure or AWS to each IP field"
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use usiem::prelude::{
+        holder::DatasetHolder,
+        ip_net::{IpNetDataset, IpNetSynDataset},
+        LogEnrichment, SiemDataset, SiemField, SiemIp, SiemLog,
+    };
+
+    use super::CloudProviderEnricher;
+    use crate::enrichment::field_filter::FieldFilter;
+
+    #[test]
+    fn should_only_enrich_selected_fields() {
+        let mut dataset = IpNetDataset::new();
+        dataset.insert(
+            SiemIp::from_ip_str("20.0.0.0").unwrap(),
+            8,
+            "Azure-westeurope",
+        );
+        let (sender, _receiver) = usiem::crossbeam_channel::unbounded();
+        let datasets = DatasetHolder::from_datasets(vec![SiemDataset::IpCloudProvider(
+            IpNetSynDataset::new(Arc::new(dataset), sender),
+        )]);
+        let mut log = SiemLog::new("", 0, "");
+        log.add_field(
+            "source.ip",
+            SiemField::IP(SiemIp::from_ip_str("20.1.1.1").unwrap()),
+        );
+        log.add_field(
+            "observer.ip",
+            SiemField::IP(SiemIp::from_ip_str("20.1.1.2").unwrap()),
+        );
+
+        let enricher = CloudProviderEnricher::default();
+        let enriched = enricher.enrich(log.clone(), &datasets);
+        assert!(enriched.has_field("source.ip.cloud.provider"));
+        assert!(enriched.has_field("observer.ip.cloud.provider"));
+
+        let enricher = CloudProviderEnricher::new(FieldFilter::deny_only(vec!["observer.*"]));
+        let enriched = enricher.enrich(log, &datasets);
+        assert!(enriched.has_field("source.ip.cloud.provider"));
+        assert!(!enriched.has_field("observer.ip.cloud.provider"));
+    }
+}
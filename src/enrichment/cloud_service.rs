@@ -3,8 +3,23 @@ use usiem::{
     utilities::types::LogString,
 };
 
-#[derive(Clone)]
-pub struct CloudServiceEnricher {}
+use super::field_filter::FieldFilter;
+
+/// Configuration key with the IP fields to check against the IpCloudService dataset
+pub const CLOUD_SERVICE_ALLOW_FIELDS: &str = "CLOUD_SERVICE_ENRICHER_ALLOW_FIELDS";
+/// Configuration key with the IP fields ignored by the CloudServiceEnricher
+pub const CLOUD_SERVICE_DENY_FIELDS: &str = "CLOUD_SERVICE_ENRICHER_DENY_FIELDS";
+
+#[derive(Clone, Default)]
+pub struct CloudServiceEnricher {
+    fields: FieldFilter,
+}
+
+impl CloudServiceEnricher {
+    pub const fn new(fields: FieldFilter) -> Self {
+        Self { fields }
+    }
+}
 
 impl LogEnrichment for CloudServiceEnricher {
     fn enrich(
@@ -19,8 +34,16 @@ impl LogEnrichment for CloudServiceEnricher {
             },
             None => return log,
         };
+        let fields = self.fields.resolve(
+            datasets,
+            CLOUD_SERVICE_ALLOW_FIELDS,
+            CLOUD_SERVICE_DENY_FIELDS,
+        );
         let mut new_fields = Vec::with_capacity(32);
         for (field_name, ip_field) in log.ip_fields() {
+            if !fields.is_allowed(field_name) {
+                continue;
+            }
             let ip: &SiemIp = match ip_field.try_into() {
                 Ok(v) => v,
                 Err(_) => continue,
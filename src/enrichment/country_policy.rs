@@ -0,0 +1,244 @@
+use usiem::{
+    prelude::{
+        geo_ip::GeoIpSynDataset, holder::DatasetHolder, text_map::TextMapSynDataset, LogEnrichment,
+        SiemDatasetType, SiemField, SiemIp, SiemLog,
+    },
+    utilities::types::LogString,
+};
+
+use super::field_filter::FieldFilter;
+
+/// Configuration key with the comma separated ISO codes of the countries allowed. Any other country breaks the policy.
+pub const GEO_POLICY_ALLOW_COUNTRIES: &str = "GEO_POLICY_ALLOW_COUNTRIES";
+/// Configuration key with the comma separated ISO codes of the countries that break the policy
+pub const GEO_POLICY_DENY_COUNTRIES: &str = "GEO_POLICY_DENY_COUNTRIES";
+/// Configuration key with the IP fields checked against the country policy
+pub const COUNTRY_POLICY_ALLOW_FIELDS: &str = "COUNTRY_POLICY_ENRICHER_ALLOW_FIELDS";
+/// Configuration key with the IP fields excluded from the country policy
+pub const COUNTRY_POLICY_DENY_FIELDS: &str = "COUNTRY_POLICY_ENRICHER_DENY_FIELDS";
+/// IP fields checked by `CountryPolicyEnricher::default()`: the origin of logins and requests
+pub const COUNTRY_POLICY_DEFAULT_FIELDS: [&str; 2] = ["source.ip", "client.ip"];
+/// Tag added to the events that break the country policy
+pub const GEO_POLICY_VIOLATION_TAG: &str = "geo_policy_violation";
+
+/// Tags the events with an IP located in a country outside the allow list or inside the deny list.
+/// The countries are looked up in the GeoIp dataset, so it does not depend on the GeoIpEnricher.
+///
+/// The default enricher only checks `source.ip` and `client.ip`, so outbound connections to
+/// foreign destinations do not break the policy. Other fields are checked with `new`, for example
+/// `CountryPolicyEnricher::new(FieldFilter::ALL)`.
+#[derive(Clone)]
+pub struct CountryPolicyEnricher {
+    fields: FieldFilter,
+}
+
+impl CountryPolicyEnricher {
+    pub const fn new(fields: FieldFilter) -> Self {
+        Self { fields }
+    }
+}
+
+impl Default for CountryPolicyEnricher {
+    fn default() -> Self {
+        Self::new(FieldFilter::allow_only(
+            COUNTRY_POLICY_DEFAULT_FIELDS.to_vec(),
+        ))
+    }
+}
+
+impl LogEnrichment for CountryPolicyEnricher {
+    fn enrich(&self, mut log: SiemLog, datasets: &DatasetHolder) -> SiemLog {
+        let geo_ip: &GeoIpSynDataset = match datasets
+            .get(&SiemDatasetType::GeoIp)
+            .and_then(|v| v.try_into().ok())
+        {
+            Some(v) => v,
+            None => return log,
+        };
+        let config: &TextMapSynDataset = match datasets
+            .get(&SiemDatasetType::Configuration)
+            .and_then(|v| v.try_into().ok())
+        {
+            Some(v) => v,
+            None => return log,
+        };
+        let allowed = country_list(config, GEO_POLICY_ALLOW_COUNTRIES);
+        let denied = country_list(config, GEO_POLICY_DENY_COUNTRIES);
+        if allowed.is_empty() && denied.is_empty() {
+            return log;
+        }
+        let fields = self.fields.resolve(
+            datasets,
+            COUNTRY_POLICY_ALLOW_FIELDS,
+            COUNTRY_POLICY_DENY_FIELDS,
+        );
+        let mut violation = None;
+        for (field_name, ip_field) in log.ip_fields() {
+            if !fields.is_allowed(field_name) {
+                continue;
+            }
+            let ip: &SiemIp = match ip_field.try_into() {
+                Ok(v) => v,
+                Err(_) => continue,
+            };
+            let country_iso = match geo_ip.get(ip) {
+                Some(info) if !info.country_iso.is_empty() => info.country_iso.clone(),
+                _ => continue,
+            };
+            let breaks_policy = (!allowed.is_empty()
+                && !allowed.iter().any(|v| v.eq_ignore_ascii_case(&country_iso)))
+                || denied.iter().any(|v| v.eq_ignore_ascii_case(&country_iso));
+            if breaks_policy {
+                violation = Some((field_name.clone(), country_iso));
+                break;
+            }
+        }
+        if let Some((field_name, country_iso)) = violation {
+            log.add_tag(GEO_POLICY_VIOLATION_TAG);
+            log.insert(
+                LogString::Borrowed("policy.geo.violation"),
+                SiemField::Text(LogString::Borrowed("true")),
+            );
+            log.insert(
+                LogString::Borrowed("policy.geo.field"),
+                SiemField::Text(field_name),
+            );
+            log.insert(
+                LogString::Borrowed("policy.geo.country_iso_code"),
+                SiemField::Text(country_iso),
+            );
+        }
+        log
+    }
+
+    fn name(&self) -> &'static str {
+        "CountryPolicyEnricher"
+    }
+
+    fn description(&self) -> &'static str {
+        "Tags the events with IPs located in countries not allowed by the configured policy"
+    }
+}
+
+fn country_list<'a>(config: &'a TextMapSynDataset, key: &'static str) -> Vec<&'a str> {
+    match config.get(&LogString::Borrowed(key)) {
+        Some(list) => list
+            .split(',')
+            .map(|v| v.trim())
+            .filter(|v| !v.is_empty())
+            .collect(),
+        None => Vec::new(),
+    }
+}
+
+#[cfg(all(test, not(feature = "slow_geoip")))]
+mod tests {
+    use std::sync::Arc;
+
+    use usiem::prelude::{
+        geo_ip::{GeoIpDataset, GeoIpInfo},
+        text_map::TextMapDataset,
+        SiemDataset,
+    };
+
+    use super::*;
+
+    fn datasets(config: &[(&'static str, &'static str)]) -> DatasetHolder {
+        let mut geo_ip = GeoIpDataset::new();
+        for (ip, country_iso) in [(0x01000000, "US"), (0x02000000, "ES"), (0x03000000, "RU")] {
+            geo_ip.insert(
+                SiemIp::V4(ip),
+                24,
+                GeoIpInfo {
+                    country_iso: LogString::Borrowed(country_iso),
+                    ..Default::default()
+                },
+            );
+        }
+        let mut configuration = TextMapDataset::new();
+        for (key, value) in config {
+            configuration.insert(*key, *value);
+        }
+        let (sender, _receiver) = usiem::crossbeam_channel::unbounded();
+        let (config_sender, _receiver) = usiem::crossbeam_channel::unbounded();
+        DatasetHolder::from_datasets(vec![
+            SiemDataset::GeoIp(GeoIpSynDataset::new(Arc::new(geo_ip), sender)),
+            SiemDataset::Configuration(TextMapSynDataset::new(
+                Arc::new(configuration),
+                config_sender,
+            )),
+        ])
+    }
+
+    fn login(source: &str) -> SiemLog {
+        let mut log = SiemLog::new("", 0, "");
+        log.add_field(
+            "source.ip",
+            SiemField::IP(SiemIp::from_ip_str(source).unwrap()),
+        );
+        log
+    }
+
+    #[test]
+    fn should_tag_countries_outside_the_allow_list() {
+        let datasets = datasets(&[(GEO_POLICY_ALLOW_COUNTRIES, "es, US")]);
+        let enricher = CountryPolicyEnricher::default();
+        let log = enricher.enrich(login("2.0.0.1"), &datasets);
+        assert!(!log.has_tag(GEO_POLICY_VIOLATION_TAG));
+        let log = enricher.enrich(login("3.0.0.1"), &datasets);
+        assert!(log.has_tag(GEO_POLICY_VIOLATION_TAG));
+        assert_eq!(
+            Some(&SiemField::Text(LogString::Borrowed("source.ip"))),
+            log.field("policy.geo.field")
+        );
+        assert_eq!(
+            Some(&SiemField::Text(LogString::Borrowed("RU"))),
+            log.field("policy.geo.country_iso_code")
+        );
+        // Unknown locations do not break the policy
+        let log = enricher.enrich(login("4.0.0.1"), &datasets);
+        assert!(!log.has_field("policy.geo.violation"));
+    }
+
+    #[test]
+    fn should_tag_countries_in_the_deny_list() {
+        let datasets = datasets(&[(GEO_POLICY_DENY_COUNTRIES, "RU")]);
+        let enricher = CountryPolicyEnricher::default();
+        let log = enricher.enrich(login("1.0.0.1"), &datasets);
+        assert!(!log.has_tag(GEO_POLICY_VIOLATION_TAG));
+        let log = enricher.enrich(login("3.0.0.1"), &datasets);
+        assert!(log.has_tag(GEO_POLICY_VIOLATION_TAG));
+        let enricher = CountryPolicyEnricher::new(FieldFilter::deny_only(vec!["source.ip"]));
+        let log = enricher.enrich(login("3.0.0.1"), &datasets);
+        assert!(!log.has_tag(GEO_POLICY_VIOLATION_TAG));
+    }
+
+    #[test]
+    fn should_only_check_the_origin_by_default() {
+        let datasets = datasets(&[(GEO_POLICY_ALLOW_COUNTRIES, "ES")]);
+        let mut log = login("2.0.0.1");
+        log.add_field(
+            "destination.ip",
+            SiemField::IP(SiemIp::from_ip_str("3.0.0.1").unwrap()),
+        );
+        log.add_field(
+            "observer.ip",
+            SiemField::IP(SiemIp::from_ip_str("1.0.0.1").unwrap()),
+        );
+        let enriched = CountryPolicyEnricher::default().enrich(log.clone(), &datasets);
+        assert!(!enriched.has_tag(GEO_POLICY_VIOLATION_TAG));
+        assert!(!enriched.has_field("policy.geo.violation"));
+
+        let mut client = SiemLog::new("", 0, "");
+        client.add_field(
+            "client.ip",
+            SiemField::IP(SiemIp::from_ip_str("3.0.0.1").unwrap()),
+        );
+        let enriched = CountryPolicyEnricher::default().enrich(client, &datasets);
+        assert!(enriched.has_tag(GEO_POLICY_VIOLATION_TAG));
+
+        // Other fields are an explicit opt-in
+        let enriched = CountryPolicyEnricher::new(FieldFilter::ALL).enrich(log, &datasets);
+        assert!(enriched.has_tag(GEO_POLICY_VIOLATION_TAG));
+    }
+}
@@ -0,0 +1,192 @@
+use usiem::{
+    prelude::{holder::DatasetHolder, text_map::TextMapSynDataset, SiemDatasetType},
+    utilities::types::LogString,
+};
+
+/// Limits which IP fields an enricher processes.
+///
+/// Patterns are field names or globs (`*` matches any sequence of characters and `?` a single one).
+/// A field is accepted when it matches an allow pattern (or the allow list is empty) and
+/// does not match any deny pattern.
+#[derive(Clone, Debug, Default)]
+pub struct FieldFilter {
+    allow: Vec<String>,
+    deny: Vec<String>,
+}
+
+impl FieldFilter {
+    /// Processes every IP field, like `FieldFilter::default()` but usable in constants
+    pub const ALL: Self = Self::new(Vec::new(), Vec::new());
+
+    pub const fn new(allow: Vec<String>, deny: Vec<String>) -> Self {
+        Self { allow, deny }
+    }
+    /// Only process the fields that match one of the patterns
+    pub fn allow_only<S: Into<String>>(patterns: Vec<S>) -> Self {
+        Self::new(patterns.into_iter().map(|v| v.into()).collect(), Vec::new())
+    }
+    /// Process all the fields except the ones that match one of the patterns
+    pub fn deny_only<S: Into<String>>(patterns: Vec<S>) -> Self {
+        Self::new(Vec::new(), patterns.into_iter().map(|v| v.into()).collect())
+    }
+
+    pub fn is_allowed(&self, field: &str) -> bool {
+        is_allowed(
+            self.allow.iter().map(|v| &v[..]),
+            self.deny.iter().map(|v| &v[..]),
+            field,
+        )
+    }
+
+    /// Combines this filter with the comma separated pattern lists stored in the Configuration dataset under `allow_key` and `deny_key`.
+    /// A field must be accepted by both filters.
+    pub fn resolve<'a>(
+        &'a self,
+        datasets: &'a DatasetHolder,
+        allow_key: &'static str,
+        deny_key: &'static str,
+    ) -> ResolvedFieldFilter<'a> {
+        let config: Option<&TextMapSynDataset> = datasets
+            .get(&SiemDatasetType::Configuration)
+            .and_then(|v| v.try_into().ok());
+        let (allow, deny) = match config {
+            Some(config) => (
+                config.get(&LogString::Borrowed(allow_key)),
+                config.get(&LogString::Borrowed(deny_key)),
+            ),
+            None => (None, None),
+        };
+        ResolvedFieldFilter {
+            filter: self,
+            allow: split_patterns(allow).collect(),
+            deny: split_patterns(deny).collect(),
+        }
+    }
+}
+
+/// Field filter of an enricher merged with the patterns of the Configuration dataset.
+/// The lists are split once, so checking each field only matches the patterns.
+pub struct ResolvedFieldFilter<'a> {
+    filter: &'a FieldFilter,
+    allow: Vec<&'a str>,
+    deny: Vec<&'a str>,
+}
+
+impl<'a> ResolvedFieldFilter<'a> {
+    pub fn is_allowed(&self, field: &str) -> bool {
+        if !self.filter.is_allowed(field) {
+            return false;
+        }
+        is_allowed(self.allow.iter().copied(), self.deny.iter().copied(), field)
+    }
+}
+
+fn split_patterns(list: Option<&LogString>) -> impl Iterator<Item = &str> {
+    list.map(|v| &v[..])
+        .unwrap_or_default()
+        .split(',')
+        .map(|v| v.trim())
+        .filter(|v| !v.is_empty())
+}
+
+fn is_allowed<'a>(
+    allow: impl Iterator<Item = &'a str>,
+    mut deny: impl Iterator<Item = &'a str>,
+    field: &str,
+) -> bool {
+    let mut allow = allow.peekable();
+    if allow.peek().is_some() && !allow.any(|pattern| glob_match(pattern, field)) {
+        return false;
+    }
+    !deny.any(|pattern| glob_match(pattern, field))
+}
+
+/// Matches `text` against a glob `pattern` supporting `*` and `?`
+pub fn glob_match(pattern: &str, text: &str) -> bool {
+    let pattern = pattern.as_bytes();
+    let text = text.as_bytes();
+    let (mut p, mut t) = (0, 0);
+    let mut last_star: Option<(usize, usize)> = None;
+    while t < text.len() {
+        if p < pattern.len() && (pattern[p] == b'?' || pattern[p] == text[t]) {
+            p += 1;
+            t += 1;
+        } else if p < pattern.len() && pattern[p] == b'*' {
+            last_star = Some((p, t));
+            p += 1;
+        } else if let Some((star_p, star_t)) = last_star {
+            p = star_p + 1;
+            t = star_t + 1;
+            last_star = Some((star_p, star_t + 1));
+        } else {
+            return false;
+        }
+    }
+    while p < pattern.len() && pattern[p] == b'*' {
+        p += 1;
+    }
+    p == pattern.len()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use usiem::prelude::{
+        holder::DatasetHolder,
+        text_map::{TextMapDataset, TextMapSynDataset},
+        SiemDataset,
+    };
+
+    use super::*;
+
+    #[test]
+    fn should_match_globs() {
+        assert!(glob_match("source.ip", "source.ip"));
+        assert!(glob_match("*.ip", "destination.nat.ip"));
+        assert!(glob_match("source.*", "source.ip"));
+        assert!(glob_match("s?urce.ip", "source.ip"));
+        assert!(glob_match("*", ""));
+        assert!(!glob_match("source.ip", "source.ip2"));
+        assert!(!glob_match("*.ip", "source.ip.port"));
+        assert!(!glob_match("host.*", "source.ip"));
+    }
+
+    #[test]
+    fn should_filter_fields() {
+        let filter = FieldFilter::default();
+        assert!(filter.is_allowed("observer.ip"));
+        const ALL: FieldFilter = FieldFilter::ALL;
+        assert!(ALL.is_allowed("observer.ip"));
+
+        let filter = FieldFilter::deny_only(vec!["observer.ip", "host.*"]);
+        assert!(filter.is_allowed("source.ip"));
+        assert!(!filter.is_allowed("observer.ip"));
+        assert!(!filter.is_allowed("host.ip"));
+
+        let filter = FieldFilter::new(vec!["*.ip".into()], vec!["host.ip".into()]);
+        assert!(filter.is_allowed("source.ip"));
+        assert!(!filter.is_allowed("host.ip"));
+        assert!(!filter.is_allowed("source.nat.address"));
+    }
+
+    #[test]
+    fn should_combine_with_configuration() {
+        let mut config = TextMapDataset::new();
+        config.insert("TEST_ALLOW_FIELDS", "source.ip, destination.ip");
+        config.insert("TEST_DENY_FIELDS", "destination.*");
+        let (sender, _receiver) = usiem::crossbeam_channel::unbounded();
+        let datasets = DatasetHolder::from_datasets(vec![SiemDataset::Configuration(
+            TextMapSynDataset::new(Arc::new(config), sender),
+        )]);
+        let filter = FieldFilter::deny_only(vec!["source.ip"]);
+        let resolved = filter.resolve(&datasets, "TEST_ALLOW_FIELDS", "TEST_DENY_FIELDS");
+        assert!(!resolved.is_allowed("source.ip"));
+        assert!(!resolved.is_allowed("destination.ip"));
+        assert!(!resolved.is_allowed("observer.ip"));
+
+        let filter = FieldFilter::default();
+        let resolved = filter.resolve(&datasets, "OTHER_ALLOW_FIELDS", "OTHER_DENY_FIELDS");
+        assert!(resolved.is_allowed("observer.ip"));
+    }
+}
@@ -1,10 +1,38 @@
 use usiem::{
-    prelude::{geo_ip::GeoIpSynDataset, LogEnrichment, SiemDatasetType, SiemField, SiemIp},
+    prelude::{
+        geo_ip::GeoIpSynDataset, text_map::TextMapSynDataset, try_to_custom_map_text_ref,
+        LogEnrichment, SiemDatasetType, SiemField, SiemIp,
+    },
     utilities::types::LogString,
 };
 
-#[derive(Clone)]
-pub struct GeoIpEnricher {}
+use super::field_filter::FieldFilter;
+use crate::{asn_type::ASN_TYPE_DATASET, common::haversine_distance};
+
+/// Configuration key with the IP fields (or glob patterns) to geolocate, separated by commas
+pub const GEOIP_ALLOW_FIELDS: &str = "GEOIP_ENRICHER_ALLOW_FIELDS";
+/// Configuration key with the IP fields that must never be geolocated, like `observer.ip`
+pub const GEOIP_DENY_FIELDS: &str = "GEOIP_ENRICHER_DENY_FIELDS";
+/// Tag added to flows between IPs located in different countries
+pub const CROSS_BORDER_TAG: &str = "cross_border";
+
+/// Country and coordinates of the source or destination IP of a flow
+struct FlowEndpoint {
+    country_iso: LogString,
+    latitude: f32,
+    longitude: f32,
+}
+
+#[derive(Clone, Default)]
+pub struct GeoIpEnricher {
+    fields: FieldFilter,
+}
+
+impl GeoIpEnricher {
+    pub const fn new(fields: FieldFilter) -> Self {
+        Self { fields }
+    }
+}
 
 impl LogEnrichment for GeoIpEnricher {
     fn enrich(
@@ -19,31 +47,59 @@ impl LogEnrichment for GeoIpEnricher {
             },
             None => return log,
         };
+        let asn_types: Option<&TextMapSynDataset> = datasets
+            .get(&SiemDatasetType::CustomMapText(LogString::Borrowed(
+                ASN_TYPE_DATASET,
+            )))
+            .and_then(|v| try_to_custom_map_text_ref(v, ASN_TYPE_DATASET).ok());
+        let fields = self
+            .fields
+            .resolve(datasets, GEOIP_ALLOW_FIELDS, GEOIP_DENY_FIELDS);
         let mut new_fields = Vec::with_capacity(32);
+        let mut source = None;
+        let mut destination = None;
         for (field_name, ip_field) in log.ip_fields() {
+            if !fields.is_allowed(field_name) {
+                continue;
+            }
             let ip: &SiemIp = match ip_field.try_into() {
                 Ok(v) => v,
                 Err(_) => continue,
             };
             match geo_ip.get(ip) {
                 Some(geoip_info) => {
-                    if geoip_info.city.len() > 0 {
+                    let endpoint = match &field_name[..] {
+                        "source.ip" => Some(&mut source),
+                        "destination.ip" => Some(&mut destination),
+                        _ => None,
+                    };
+                    if let Some(endpoint) = endpoint {
+                        *endpoint = Some(FlowEndpoint {
+                            country_iso: geoip_info.country_iso.clone(),
+                            latitude: geoip_info.latitude,
+                            longitude: geoip_info.longitude,
+                        });
+                    }
+                    if !geoip_info.city.is_empty() {
                         new_fields.push((
                             LogString::Owned(format!("{}.geo.city_name", &field_name[..])),
                             SiemField::Text(geoip_info.city.clone()),
                         ));
                     }
-                    if geoip_info.country.len() > 0 {
+                    if !geoip_info.country.is_empty() {
                         new_fields.push((
                             LogString::Owned(format!("{}.geo.country_name", &field_name[..])),
                             SiemField::Text(geoip_info.country.clone()),
                         ));
+                    }
+                    // Some providers like DB-IP only have the ISO code of the country
+                    if !geoip_info.country_iso.is_empty() {
                         new_fields.push((
                             LogString::Owned(format!("{}.geo.country_iso_code", &field_name[..])),
                             SiemField::Text(geoip_info.country_iso.clone()),
                         ));
                     }
-                    if geoip_info.isp.len() > 0 {
+                    if !geoip_info.isp.is_empty() {
                         new_fields.push((
                             LogString::Owned(format!("{}.as.organization.name", &field_name[..])),
                             SiemField::Text(geoip_info.isp.clone()),
@@ -54,6 +110,14 @@ impl LogEnrichment for GeoIpEnricher {
                             LogString::Owned(format!("{}.as.number", &field_name[..])),
                             SiemField::U64(geoip_info.asn as u64),
                         ));
+                        if let Some(asn_type) =
+                            asn_types.and_then(|v| v.get(&geoip_info.asn.to_string()))
+                        {
+                            new_fields.push((
+                                LogString::Owned(format!("{}.as.type", &field_name[..])),
+                                SiemField::Text(asn_type.clone()),
+                            ));
+                        }
                     }
                     if geoip_info.longitude != 0.0 && geoip_info.latitude != 0.0 {
                         new_fields.push((
@@ -69,6 +133,11 @@ impl LogEnrichment for GeoIpEnricher {
                 None => continue,
             };
         }
+        if let (Some(source), Some(destination)) = (source, destination) {
+            if flow_fields(&source, &destination, &mut new_fields) {
+                log.add_tag(CROSS_BORDER_TAG);
+            }
+        }
         for (field_name, field_value) in new_fields {
             log.insert(field_name, field_value);
         }
@@ -83,3 +152,165 @@ impl LogEnrichment for GeoIpEnricher {
         "Adds geo ip information to each IP field"
     }
 }
+
+/// Adds the distance, cross border flag and country pair of a flow. Returns true if the flow crosses a border.
+fn flow_fields(
+    source: &FlowEndpoint,
+    destination: &FlowEndpoint,
+    new_fields: &mut Vec<(LogString, SiemField)>,
+) -> bool {
+    let has_location = |v: &FlowEndpoint| v.latitude != 0.0 && v.longitude != 0.0;
+    if has_location(source) && has_location(destination) {
+        new_fields.push((
+            LogString::Borrowed("network.geo.distance_km"),
+            SiemField::F64(haversine_distance(
+                source.latitude as f64,
+                source.longitude as f64,
+                destination.latitude as f64,
+                destination.longitude as f64,
+            )),
+        ));
+    }
+    if source.country_iso.is_empty() || destination.country_iso.is_empty() {
+        return false;
+    }
+    let cross_border = source.country_iso != destination.country_iso;
+    new_fields.push((
+        LogString::Borrowed("network.cross_border"),
+        SiemField::Text(LogString::Borrowed(if cross_border {
+            "true"
+        } else {
+            "false"
+        })),
+    ));
+    let (first, second) = if source.country_iso <= destination.country_iso {
+        (&source.country_iso, &destination.country_iso)
+    } else {
+        (&destination.country_iso, &source.country_iso)
+    };
+    new_fields.push((
+        LogString::Borrowed("network.country_pair"),
+        SiemField::Text(LogString::Owned(format!("{}-{}", first, second))),
+    ));
+    cross_border
+}
+
+#[cfg(all(test, not(feature = "slow_geoip")))]
+mod tests {
+    use std::sync::Arc;
+
+    use usiem::prelude::{
+        geo_ip::{GeoIpDataset, GeoIpInfo},
+        holder::DatasetHolder,
+        text_map::TextMapDataset,
+        SiemDataset, SiemLog,
+    };
+
+    use super::*;
+
+    fn info(country_iso: &'static str, latitude: f32, longitude: f32) -> GeoIpInfo {
+        GeoIpInfo {
+            country_iso: LogString::Borrowed(country_iso),
+            country: LogString::Borrowed(country_iso),
+            latitude,
+            longitude,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn should_add_flow_distance_and_country_pair() {
+        let mut dataset = GeoIpDataset::new();
+        dataset.insert(SiemIp::V4(0x01000000), 24, info("US", 40.7128, -74.006));
+        dataset.insert(SiemIp::V4(0x02000000), 24, info("ES", 40.4168, -3.7038));
+        dataset.insert(SiemIp::V4(0x03000000), 24, info("ES", 41.3874, 2.1686));
+        let (sender, _receiver) = usiem::crossbeam_channel::unbounded();
+        let datasets = DatasetHolder::from_datasets(vec![SiemDataset::GeoIp(
+            GeoIpSynDataset::new(Arc::new(dataset), sender),
+        )]);
+        let flow = |source: &str, destination: &str| {
+            let mut log = SiemLog::new("", 0, "");
+            log.add_field(
+                "source.ip",
+                SiemField::IP(SiemIp::from_ip_str(source).unwrap()),
+            );
+            log.add_field(
+                "destination.ip",
+                SiemField::IP(SiemIp::from_ip_str(destination).unwrap()),
+            );
+            GeoIpEnricher::default().enrich(log, &datasets)
+        };
+        let log = flow("2.0.0.1", "1.0.0.1");
+        assert!(log.has_tag(CROSS_BORDER_TAG));
+        assert_eq!(
+            Some(&SiemField::Text(LogString::Borrowed("ES-US"))),
+            log.field("network.country_pair")
+        );
+        assert_eq!(
+            Some(&SiemField::Text(LogString::Borrowed("true"))),
+            log.field("network.cross_border")
+        );
+        match log.field("network.geo.distance_km") {
+            Some(SiemField::F64(distance)) => assert!((distance - 5768.0).abs() < 10.0),
+            _ => panic!("Distance field not found"),
+        }
+
+        let log = flow("2.0.0.1", "3.0.0.1");
+        assert!(!log.has_tag(CROSS_BORDER_TAG));
+        assert_eq!(
+            Some(&SiemField::Text(LogString::Borrowed("ES-ES"))),
+            log.field("network.country_pair")
+        );
+
+        let log = flow("2.0.0.1", "4.0.0.1");
+        assert!(!log.has_field("network.country_pair"));
+        assert!(!log.has_field("network.geo.distance_km"));
+    }
+
+    #[test]
+    fn should_add_asn_type() {
+        let mut dataset = GeoIpDataset::new();
+        dataset.insert(
+            SiemIp::V4(0x01000000),
+            24,
+            GeoIpInfo {
+                asn: 16509,
+                ..Default::default()
+            },
+        );
+        dataset.insert(
+            SiemIp::V4(0x02000000),
+            24,
+            GeoIpInfo {
+                asn: 3352,
+                ..Default::default()
+            },
+        );
+        let mut asn_types = TextMapDataset::new();
+        asn_types.insert("16509", "hosting");
+        let (sender, _receiver) = usiem::crossbeam_channel::unbounded();
+        let (text_sender, _receiver) = usiem::crossbeam_channel::unbounded();
+        let datasets = DatasetHolder::from_datasets(vec![
+            SiemDataset::GeoIp(GeoIpSynDataset::new(Arc::new(dataset), sender)),
+            SiemDataset::CustomMapText((
+                LogString::Borrowed(ASN_TYPE_DATASET),
+                TextMapSynDataset::new(Arc::new(asn_types), text_sender),
+            )),
+        ]);
+        let mut log = SiemLog::new("", 0, "");
+        log.add_field(
+            "source.ip",
+            SiemField::IP(SiemIp::from_ip_str("1.0.0.1").unwrap()),
+        );
+        log.add_field(
+            "destination.ip",
+            SiemField::IP(SiemIp::from_ip_str("2.0.0.1").unwrap()),
+        );
+        let log = GeoIpEnricher::default().enrich(log, &datasets);
+        assert_eq!(
+            Some(&SiemField::Text(LogString::Borrowed("hosting"))),
+            log.field("source.ip.as.type")
+        );
+        assert!(!log.has_field("destination.ip.as.type"));
+    }
+}
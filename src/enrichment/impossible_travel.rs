@@ -0,0 +1,212 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+use usiem::{
+    prelude::{holder::DatasetHolder, LogEnrichment, SiemField, SiemLog},
+    utilities::types::LogString,
+};
+
+use crate::common::haversine_distance;
+
+/// Tag added to the events whose implied speed is over the threshold
+pub const IMPOSSIBLE_TRAVEL_TAG: &str = "impossible_travel";
+
+#[derive(Clone, Debug)]
+pub struct ImpossibleTravelConfig {
+    /// Field that identifies the user
+    pub user_field: String,
+    /// IP field whose `geo.location` is tracked. Must be enriched before by the GeoIpEnricher
+    pub location_field: String,
+    /// Maximum time in milliseconds between two events of the same user to compare them
+    pub window: i64,
+    /// Speed in km/h over which the travel is considered impossible
+    pub max_speed: f64,
+    /// Jumps shorter than this distance in km are ignored, as the geolocation of IPs is not precise
+    pub min_distance: f64,
+}
+
+impl Default for ImpossibleTravelConfig {
+    fn default() -> Self {
+        Self {
+            user_field: "user.name".to_string(),
+            location_field: "source.ip".to_string(),
+            window: 24 * 3_600_000,
+            max_speed: 900.0,
+            min_distance: 100.0,
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+struct LastLocation {
+    latitude: f64,
+    longitude: f64,
+    timestamp: i64,
+}
+
+#[derive(Default)]
+struct TravelState {
+    users: HashMap<String, LastLocation>,
+    last_cleanup: i64,
+}
+
+/// Tracks the last location of each user and tags the events that imply a travel faster than `max_speed`.
+/// Clones share the same state, so the enricher can be used in multiple threads.
+#[derive(Clone, Default)]
+pub struct ImpossibleTravelEnricher {
+    config: ImpossibleTravelConfig,
+    state: Arc<Mutex<TravelState>>,
+}
+
+impl ImpossibleTravelEnricher {
+    pub fn new(config: ImpossibleTravelConfig) -> Self {
+        Self {
+            config,
+            state: Arc::new(Mutex::new(TravelState::default())),
+        }
+    }
+
+    fn location(&self, log: &SiemLog) -> Option<(f64, f64)> {
+        let latitude = log.field(&format!("{}.geo.location.lat", self.config.location_field))?;
+        let longitude = log.field(&format!("{}.geo.location.lon", self.config.location_field))?;
+        match (latitude, longitude) {
+            (SiemField::F64(latitude), SiemField::F64(longitude)) => Some((*latitude, *longitude)),
+            _ => None,
+        }
+    }
+}
+
+impl LogEnrichment for ImpossibleTravelEnricher {
+    fn enrich(&self, mut log: SiemLog, _datasets: &DatasetHolder) -> SiemLog {
+        let user = match log.field(&self.config.user_field) {
+            Some(SiemField::Text(user)) if !user.is_empty() => user.to_string(),
+            _ => return log,
+        };
+        let (latitude, longitude) = match self.location(&log) {
+            Some(v) => v,
+            None => return log,
+        };
+        let timestamp = event_timestamp(&log);
+        let current = LastLocation {
+            latitude,
+            longitude,
+            timestamp,
+        };
+        let previous = {
+            let mut state = match self.state.lock() {
+                Ok(v) => v,
+                Err(poisoned) => poisoned.into_inner(),
+            };
+            if timestamp - state.last_cleanup > self.config.window {
+                let window = self.config.window;
+                state.users.retain(|_, v| timestamp - v.timestamp <= window);
+                state.last_cleanup = timestamp;
+            }
+            match state.users.get(&user).copied() {
+                // Events received out of order do not replace the latest location
+                Some(previous) if previous.timestamp > timestamp => return log,
+                previous => {
+                    state.users.insert(user, current);
+                    previous
+                }
+            }
+        };
+        let previous = match previous {
+            Some(v) if timestamp - v.timestamp <= self.config.window => v,
+            _ => return log,
+        };
+        let distance =
+            haversine_distance(previous.latitude, previous.longitude, latitude, longitude);
+        if distance < self.config.min_distance {
+            return log;
+        }
+        let hours = (timestamp - previous.timestamp) as f64 / 3_600_000.0;
+        // Simultaneous events have no finite speed, that cannot be stored in the log
+        let speed = if hours > 0.0 {
+            Some(distance / hours)
+        } else {
+            None
+        };
+        if speed.map(|v| v > self.config.max_speed).unwrap_or(true) {
+            log.add_tag(IMPOSSIBLE_TRAVEL_TAG);
+            log.insert(
+                LogString::Borrowed("impossible_travel.distance_km"),
+                SiemField::F64(distance),
+            );
+            if let Some(speed) = speed {
+                log.insert(
+                    LogString::Borrowed("impossible_travel.speed_kmh"),
+                    SiemField::F64(speed),
+                );
+            }
+        }
+        log
+    }
+
+    fn name(&self) -> &'static str {
+        "ImpossibleTravelEnricher"
+    }
+
+    fn description(&self) -> &'static str {
+        "Tags the events of users that appear in two distant locations in less time than physically possible"
+    }
+}
+
+/// `SiemLog::set_event_created` stores the date as I64 while `event_created` only reads Date fields
+fn event_timestamp(log: &SiemLog) -> i64 {
+    match log.field("event.created") {
+        Some(SiemField::Date(v)) | Some(SiemField::I64(v)) => *v,
+        _ => log.event_received(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn login(user: &str, timestamp: i64, latitude: f64, longitude: f64) -> SiemLog {
+        let mut log = SiemLog::new("", timestamp, "");
+        log.set_event_created(timestamp);
+        log.add_field("user.name", SiemField::Text(LogString::Owned(user.into())));
+        log.add_field("source.ip.geo.location.lat", SiemField::F64(latitude));
+        log.add_field("source.ip.geo.location.lon", SiemField::F64(longitude));
+        log
+    }
+
+    #[test]
+    fn should_tag_impossible_travels() {
+        let enricher = ImpossibleTravelEnricher::default();
+        let datasets = DatasetHolder::from_datasets(vec![]);
+        let hour = 3_600_000;
+        // Madrid
+        let log = enricher.enrich(login("alice", 0, 40.4168, -3.7038), &datasets);
+        assert!(!log.has_tag(IMPOSSIBLE_TRAVEL_TAG));
+        // New York one hour later
+        let log = enricher.enrich(login("alice", hour, 40.7128, -74.006), &datasets);
+        assert!(log.has_tag(IMPOSSIBLE_TRAVEL_TAG));
+        match log.field("impossible_travel.speed_kmh") {
+            Some(SiemField::F64(speed)) => assert!(*speed > 5000.0),
+            _ => panic!("Speed field not found"),
+        }
+        assert!(log.has_field("impossible_travel.distance_km"));
+        // Back to Madrid after a transatlantic flight
+        let log = enricher.enrich(login("alice", 9 * hour, 40.4168, -3.7038), &datasets);
+        assert!(!log.has_tag(IMPOSSIBLE_TRAVEL_TAG));
+        // Other users do not share state
+        let log = enricher.enrich(login("bob", 9 * hour, 40.7128, -74.006), &datasets);
+        assert!(!log.has_tag(IMPOSSIBLE_TRAVEL_TAG));
+        // Outside of the window
+        let log = enricher.enrich(login("bob", 40 * hour, 35.6762, 139.6503), &datasets);
+        assert!(!log.has_tag(IMPOSSIBLE_TRAVEL_TAG));
+        // Nearby locations are ignored
+        let log = enricher.enrich(login("bob", 40 * hour, 35.4437, 139.638), &datasets);
+        assert!(!log.has_tag(IMPOSSIBLE_TRAVEL_TAG));
+        // Two places at the same time
+        let log = enricher.enrich(login("bob", 40 * hour, 40.4168, -3.7038), &datasets);
+        assert!(log.has_tag(IMPOSSIBLE_TRAVEL_TAG));
+        assert!(log.has_field("impossible_travel.distance_km"));
+        assert!(!log.has_field("impossible_travel.speed_kmh"));
+    }
+}
@@ -1,4 +1,9 @@
 pub mod basic_ip;
 pub mod cloud_provider;
 pub mod cloud_service;
+pub mod country_policy;
+pub mod field_filter;
 pub mod geoip;
+pub mod impossible_travel;
+pub mod network_class;
+pub mod network_zone;
@@ -0,0 +1,296 @@
+use usiem::{
+    prelude::{
+        holder::DatasetHolder, text_map::TextMapSynDataset, LogEnrichment, SiemDatasetType,
+        SiemField, SiemIp, SiemLog,
+    },
+    utilities::types::LogString,
+};
+
+use super::field_filter::FieldFilter;
+use crate::{common::ip_in_network, network::parse_ip_network};
+
+/// Configuration key with the IP fields to classify
+pub const NETWORK_CLASS_ALLOW_FIELDS: &str = "NETWORK_CLASS_ENRICHER_ALLOW_FIELDS";
+/// Configuration key with the IP fields that are never classified
+pub const NETWORK_CLASS_DENY_FIELDS: &str = "NETWORK_CLASS_ENRICHER_DENY_FIELDS";
+/// Configuration key with a comma separated list of CIDRs that belong to the organization, e.g. public ranges owned by the company
+pub const INTERNAL_NETWORKS: &str = "INTERNAL_NETWORKS";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IpClass {
+    /// RFC1918 private ranges
+    Private,
+    /// RFC6598 shared address space used by carrier-grade NAT
+    Cgnat,
+    Loopback,
+    LinkLocal,
+    Multicast,
+    /// RFC5737 and RFC3849 ranges reserved for documentation
+    Documentation,
+    /// RFC4193 IPv6 unique local addresses
+    UniqueLocal,
+    /// Reserved or special purpose ranges that must never be seen on the internet
+    Bogon,
+    Public,
+}
+
+impl IpClass {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            IpClass::Private => "rfc1918",
+            IpClass::Cgnat => "cgnat",
+            IpClass::Loopback => "loopback",
+            IpClass::LinkLocal => "link_local",
+            IpClass::Multicast => "multicast",
+            IpClass::Documentation => "documentation",
+            IpClass::UniqueLocal => "ula",
+            IpClass::Bogon => "bogon",
+            IpClass::Public => "public",
+        }
+    }
+    /// The address can only be reached from inside the organization
+    pub fn is_internal(&self) -> bool {
+        matches!(
+            self,
+            IpClass::Private
+                | IpClass::Cgnat
+                | IpClass::Loopback
+                | IpClass::LinkLocal
+                | IpClass::UniqueLocal
+        )
+    }
+}
+
+const IPV4_CLASSES: [(u32, u8, IpClass); 16] = [
+    (0x0A000000, 8, IpClass::Private),        // 10.0.0.0/8
+    (0xAC100000, 12, IpClass::Private),       // 172.16.0.0/12
+    (0xC0A80000, 16, IpClass::Private),       // 192.168.0.0/16
+    (0x64400000, 10, IpClass::Cgnat),         // 100.64.0.0/10
+    (0x7F000000, 8, IpClass::Loopback),       // 127.0.0.0/8
+    (0xA9FE0000, 16, IpClass::LinkLocal),     // 169.254.0.0/16
+    (0xE0000000, 4, IpClass::Multicast),      // 224.0.0.0/4
+    (0xC0000200, 24, IpClass::Documentation), // 192.0.2.0/24
+    (0xC6336400, 24, IpClass::Documentation), // 198.51.100.0/24
+    (0xCB007100, 24, IpClass::Documentation), // 203.0.113.0/24
+    (0x00000000, 8, IpClass::Bogon),          // 0.0.0.0/8
+    (0xC0000000, 24, IpClass::Bogon),         // 192.0.0.0/24
+    (0xC0586300, 24, IpClass::Bogon),         // 192.88.99.0/24
+    (0xC6120000, 15, IpClass::Bogon),         // 198.18.0.0/15
+    (0xF0000000, 4, IpClass::Bogon),          // 240.0.0.0/4
+    (0xFFFFFFFF, 32, IpClass::Bogon),         // 255.255.255.255/32
+];
+
+const IPV6_CLASSES: [(u128, u8, IpClass); 10] = [
+    (1, 128, IpClass::Loopback),                     // ::1/128
+    (0, 128, IpClass::Bogon),                        // ::/128
+    (0xfe80 << 112, 10, IpClass::LinkLocal),         // fe80::/10
+    (0xff00 << 112, 8, IpClass::Multicast),          // ff00::/8
+    (0xfc00 << 112, 7, IpClass::UniqueLocal),        // fc00::/7
+    (0x2001_0db8 << 96, 32, IpClass::Documentation), // 2001:db8::/32
+    (0x3fff << 112, 20, IpClass::Documentation),     // 3fff::/20
+    (0x0100 << 112, 64, IpClass::Bogon),             // 100::/64
+    (0x2001_0010 << 96, 28, IpClass::Bogon),         // 2001:10::/28
+    (0xfec0 << 112, 10, IpClass::Bogon),             // fec0::/10
+];
+
+pub fn classify_ip(ip: &SiemIp) -> IpClass {
+    match ip {
+        SiemIp::V4(ip) => {
+            for (net_ip, net, class) in IPV4_CLASSES {
+                if ip_in_network(&SiemIp::V4(*ip), &SiemIp::V4(net_ip), net) {
+                    return class;
+                }
+            }
+            IpClass::Public
+        }
+        SiemIp::V6(ip) => {
+            // IPv4-mapped addresses ::ffff:0:0/96
+            if ip >> 32 == 0xffff {
+                return classify_ip(&SiemIp::V4(*ip as u32));
+            }
+            for (net_ip, net, class) in IPV6_CLASSES {
+                if ip_in_network(&SiemIp::V6(*ip), &SiemIp::V6(net_ip), net) {
+                    return class;
+                }
+            }
+            IpClass::Public
+        }
+    }
+}
+
+/// Tags each IP field with its network class (private, loopback, bogon...) and sets the ECS `network.direction` using the source and destination IPs.
+#[derive(Clone, Default)]
+pub struct NetworkClassEnricher {
+    fields: FieldFilter,
+}
+
+impl NetworkClassEnricher {
+    pub const fn new(fields: FieldFilter) -> Self {
+        Self { fields }
+    }
+}
+
+impl LogEnrichment for NetworkClassEnricher {
+    fn enrich(&self, mut log: SiemLog, datasets: &DatasetHolder) -> SiemLog {
+        let internal_networks = configured_internal_networks(datasets);
+        let is_internal = |ip: &SiemIp, class: IpClass| {
+            class.is_internal()
+                || internal_networks
+                    .iter()
+                    .any(|(net_ip, net)| ip_in_network(ip, net_ip, *net))
+        };
+        let fields = self.fields.resolve(
+            datasets,
+            NETWORK_CLASS_ALLOW_FIELDS,
+            NETWORK_CLASS_DENY_FIELDS,
+        );
+        let mut new_fields = Vec::with_capacity(32);
+        let mut source_internal = None;
+        let mut destination_internal = None;
+        for (field_name, ip_field) in log.ip_fields() {
+            let ip: &SiemIp = match ip_field.try_into() {
+                Ok(v) => v,
+                Err(_) => continue,
+            };
+            let class = classify_ip(ip);
+            if &field_name[..] == "source.ip" {
+                source_internal = Some(is_internal(ip, class));
+            } else if &field_name[..] == "destination.ip" {
+                destination_internal = Some(is_internal(ip, class));
+            }
+            if !fields.is_allowed(field_name) {
+                continue;
+            }
+            new_fields.push((
+                LogString::Owned(format!("{}.network.class", &field_name[..])),
+                SiemField::Text(LogString::Borrowed(class.as_str())),
+            ));
+        }
+        if let (Some(source), Some(destination)) = (source_internal, destination_internal) {
+            if !log.has_field("network.direction") {
+                let direction = match (source, destination) {
+                    (true, true) => "internal",
+                    (true, false) => "outbound",
+                    (false, true) => "inbound",
+                    (false, false) => "external",
+                };
+                new_fields.push((
+                    LogString::Borrowed("network.direction"),
+                    SiemField::Text(LogString::Borrowed(direction)),
+                ));
+            }
+        }
+        for (field_name, field_value) in new_fields {
+            log.insert(field_name, field_value);
+        }
+        log
+    }
+
+    fn name(&self) -> &'static str {
+        "NetworkClassEnricher"
+    }
+
+    fn description(&self) -> &'static str {
+        "Classifies each IP field as private, CGNAT, loopback, link-local, multicast, documentation, ULA, bogon or public and adds the network direction"
+    }
+}
+
+fn configured_internal_networks(datasets: &DatasetHolder) -> Vec<(SiemIp, u8)> {
+    let config: &TextMapSynDataset = match datasets
+        .get(&SiemDatasetType::Configuration)
+        .and_then(|v| v.try_into().ok())
+    {
+        Some(v) => v,
+        None => return Vec::new(),
+    };
+    match config.get(&LogString::Borrowed(INTERNAL_NETWORKS)) {
+        Some(networks) => networks
+            .split(',')
+            .filter_map(|v| parse_ip_network(v.trim()))
+            .collect(),
+        None => Vec::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use usiem::prelude::{
+        text_map::{TextMapDataset, TextMapSynDataset},
+        SiemDataset,
+    };
+
+    use super::*;
+
+    fn class_of(ip: &str) -> IpClass {
+        classify_ip(&SiemIp::from_ip_str(ip).unwrap())
+    }
+
+    #[test]
+    fn should_classify_ips() {
+        assert_eq!(IpClass::Private, class_of("10.1.2.3"));
+        assert_eq!(IpClass::Private, class_of("172.31.255.255"));
+        assert_eq!(IpClass::Public, class_of("172.32.0.1"));
+        assert_eq!(IpClass::Private, class_of("192.168.1.1"));
+        assert_eq!(IpClass::Cgnat, class_of("100.127.0.1"));
+        assert_eq!(IpClass::Loopback, class_of("127.0.0.1"));
+        assert_eq!(IpClass::LinkLocal, class_of("169.254.10.1"));
+        assert_eq!(IpClass::Multicast, class_of("239.255.255.250"));
+        assert_eq!(IpClass::Documentation, class_of("203.0.113.7"));
+        assert_eq!(IpClass::Bogon, class_of("0.1.2.3"));
+        assert_eq!(IpClass::Bogon, class_of("250.0.0.1"));
+        assert_eq!(IpClass::Public, class_of("8.8.8.8"));
+        assert_eq!(IpClass::Loopback, class_of("::1"));
+        assert_eq!(IpClass::LinkLocal, class_of("fe80::1"));
+        assert_eq!(IpClass::UniqueLocal, class_of("fd12:3456::1"));
+        assert_eq!(IpClass::Multicast, class_of("ff02::1"));
+        assert_eq!(IpClass::Documentation, class_of("2001:db8::1"));
+        assert_eq!(IpClass::Private, class_of("::ffff:a00:1"));
+        assert_eq!(IpClass::Public, class_of("2a00:1450::1"));
+    }
+
+    #[test]
+    fn should_set_network_direction() {
+        let mut config = TextMapDataset::new();
+        config.insert(INTERNAL_NETWORKS, "8.8.4.0/24, 2a00:1450::/32");
+        let (sender, _receiver) = usiem::crossbeam_channel::unbounded();
+        let datasets = DatasetHolder::from_datasets(vec![SiemDataset::Configuration(
+            TextMapSynDataset::new(Arc::new(config), sender),
+        )]);
+        let enricher = NetworkClassEnricher::default();
+        let direction = |source: &str, destination: &str| {
+            let mut log = SiemLog::new("", 0, "");
+            log.add_field(
+                "source.ip",
+                SiemField::IP(SiemIp::from_ip_str(source).unwrap()),
+            );
+            log.add_field(
+                "destination.ip",
+                SiemField::IP(SiemIp::from_ip_str(destination).unwrap()),
+            );
+            let log = enricher.enrich(log, &datasets);
+            log.field("network.direction").map(|v| v.to_string())
+        };
+        assert_eq!(Some("outbound".into()), direction("192.168.1.1", "1.1.1.1"));
+        assert_eq!(Some("inbound".into()), direction("1.1.1.1", "10.0.0.1"));
+        assert_eq!(Some("internal".into()), direction("10.0.0.2", "8.8.4.4"));
+        assert_eq!(Some("external".into()), direction("1.1.1.1", "8.8.8.8"));
+        assert_eq!(
+            Some("internal".into()),
+            direction("2a00:1450::1", "fd00::1")
+        );
+
+        let mut log = SiemLog::new("", 0, "");
+        log.add_field(
+            "source.ip",
+            SiemField::IP(SiemIp::from_ip_str("10.0.0.1").unwrap()),
+        );
+        let log = enricher.enrich(log, &datasets);
+        assert_eq!(
+            Some(&SiemField::Text(LogString::Borrowed("rfc1918"))),
+            log.field("source.ip.network.class")
+        );
+        assert!(!log.has_field("network.direction"));
+    }
+}
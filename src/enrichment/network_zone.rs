@@ -0,0 +1,148 @@
+use usiem::{
+    prelude::{
+        holder::DatasetHolder, ip_net::IpNetSynDataset, try_to_custom_map_ip_net_ref,
+        LogEnrichment, SiemDatasetType, SiemField, SiemIp, SiemLog,
+    },
+    utilities::types::LogString,
+};
+
+use super::field_filter::FieldFilter;
+use crate::network_zone::{NETWORK_SITE_DATASET, NETWORK_VLAN_DATASET, NETWORK_ZONE_DATASET};
+
+/// Configuration key with the IP fields to label with their zone
+pub const NETWORK_ZONE_ALLOW_FIELDS: &str = "NETWORK_ZONE_ENRICHER_ALLOW_FIELDS";
+/// Configuration key with the IP fields that are never labeled with a zone
+pub const NETWORK_ZONE_DENY_FIELDS: &str = "NETWORK_ZONE_ENRICHER_DENY_FIELDS";
+
+/// Adds the zone, VLAN and site of internal networks to each IP field.
+/// Uses the `NetworkZone`, `NetworkVlan` and `NetworkSite` CustomMapIpNet datasets.
+#[derive(Clone, Default)]
+pub struct NetworkZoneEnricher {
+    fields: FieldFilter,
+}
+
+impl NetworkZoneEnricher {
+    pub const fn new(fields: FieldFilter) -> Self {
+        Self { fields }
+    }
+}
+
+impl LogEnrichment for NetworkZoneEnricher {
+    fn enrich(&self, mut log: SiemLog, datasets: &DatasetHolder) -> SiemLog {
+        let zone: Option<&IpNetSynDataset> = datasets
+            .get(&SiemDatasetType::CustomMapIpNet(LogString::Borrowed(
+                NETWORK_ZONE_DATASET,
+            )))
+            .and_then(|v| try_to_custom_map_ip_net_ref(v, NETWORK_ZONE_DATASET).ok());
+        let vlan: Option<&IpNetSynDataset> = datasets
+            .get(&SiemDatasetType::CustomMapIpNet(LogString::Borrowed(
+                NETWORK_VLAN_DATASET,
+            )))
+            .and_then(|v| try_to_custom_map_ip_net_ref(v, NETWORK_VLAN_DATASET).ok());
+        let site: Option<&IpNetSynDataset> = datasets
+            .get(&SiemDatasetType::CustomMapIpNet(LogString::Borrowed(
+                NETWORK_SITE_DATASET,
+            )))
+            .and_then(|v| try_to_custom_map_ip_net_ref(v, NETWORK_SITE_DATASET).ok());
+        if zone.is_none() && vlan.is_none() && site.is_none() {
+            return log;
+        }
+        let fields = self.fields.resolve(
+            datasets,
+            NETWORK_ZONE_ALLOW_FIELDS,
+            NETWORK_ZONE_DENY_FIELDS,
+        );
+        let mut new_fields = Vec::with_capacity(32);
+        for (field_name, ip_field) in log.ip_fields() {
+            if !fields.is_allowed(field_name) {
+                continue;
+            }
+            let ip: &SiemIp = match ip_field.try_into() {
+                Ok(v) => v,
+                Err(_) => continue,
+            };
+            for (dataset, suffix) in [
+                (zone, "network.zone"),
+                (vlan, "network.vlan"),
+                (site, "geo.name"),
+            ] {
+                if let Some(value) = dataset.and_then(|v| v.get(ip)) {
+                    new_fields.push((
+                        LogString::Owned(format!("{}.{}", &field_name[..], suffix)),
+                        SiemField::Text(value.clone()),
+                    ));
+                }
+            }
+        }
+        for (field_name, field_value) in new_fields {
+            log.insert(field_name, field_value);
+        }
+        log
+    }
+
+    fn name(&self) -> &'static str {
+        "NetworkZoneEnricher"
+    }
+
+    fn description(&self) -> &'static str {
+        "Adds the network zone, VLAN and site name of internal networks to each IP field"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use usiem::prelude::{holder::DatasetHolder, ip_net::IpNetDataset, SiemDataset};
+
+    use super::*;
+    use crate::network_zone::{build_network_zone_datasets, NetworkZoneRecord};
+
+    #[test]
+    fn should_label_ip_fields() {
+        let built = build_network_zone_datasets(&[NetworkZoneRecord {
+            network: "10.40.0.0/16".into(),
+            zone: "PCI".into(),
+            vlan: "40".into(),
+            site: "Madrid".into(),
+        }]);
+        let mut headquarters = IpNetDataset::new();
+        headquarters.insert(
+            SiemIp::V4(0x0a28_0000),
+            16,
+            LogString::Borrowed("Headquarters"),
+        );
+        let (sender, _receiver) = usiem::crossbeam_channel::unbounded();
+        let datasets = DatasetHolder::from_datasets(vec![
+            SiemDataset::CustomMapIpNet((
+                LogString::Borrowed(NETWORK_ZONE_DATASET),
+                IpNetSynDataset::new(Arc::new(built.zone), sender.clone()),
+            )),
+            SiemDataset::CustomMapIpNet((
+                LogString::Borrowed(NETWORK_VLAN_DATASET),
+                IpNetSynDataset::new(Arc::new(built.vlan), sender.clone()),
+            )),
+            SiemDataset::CustomMapIpNet((
+                LogString::Borrowed(NETWORK_SITE_DATASET),
+                IpNetSynDataset::new(Arc::new(built.site), sender.clone()),
+            )),
+            // Other components keep their own data in IpHeadquarters
+            SiemDataset::IpHeadquarters(IpNetSynDataset::new(Arc::new(headquarters), sender)),
+        ]);
+        let mut log = SiemLog::new("", 0, "");
+        log.add_field(
+            "destination.ip",
+            SiemField::IP(SiemIp::from_ip_str("10.40.1.1").unwrap()),
+        );
+        log.add_field(
+            "source.ip",
+            SiemField::IP(SiemIp::from_ip_str("8.8.8.8").unwrap()),
+        );
+        let log = NetworkZoneEnricher::default().enrich(log, &datasets);
+        let text = |name: &str| log.field(name).map(|v| v.to_string());
+        assert_eq!(Some("PCI".into()), text("destination.ip.network.zone"));
+        assert_eq!(Some("40".into()), text("destination.ip.network.vlan"));
+        assert_eq!(Some("Madrid".into()), text("destination.ip.geo.name"));
+        assert_eq!(None, text("source.ip.network.zone"));
+    }
+}
@@ -0,0 +1,713 @@
+use std::{
+    future::Future,
+    path::{Path, PathBuf},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use reqwest::{
+    header::{ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED},
+    StatusCode,
+};
+use serde::{Deserialize, Serialize};
+use usiem::{prelude::text_map::TextMapSynDataset, utilities::types::LogString};
+
+use crate::err::{ErrContext, TempErr, TempResult};
+
+/// Configuration key with the folder where the downloaded feeds are kept between updates.
+/// Defaults to `usiem_feed_cache` inside the temporal folder.
+pub const FEED_CACHE_DIR: &str = "FEED_CACHE_DIR";
+
+/// Configuration key with the URL of the proxy used for every download, like `http://proxy.local:3128`
+pub const FEED_PROXY: &str = "FEED_PROXY";
+/// Secret with the user of the proxy. Read from the Secrets dataset of each task.
+pub const FEED_PROXY_USER: &str = "FEED_PROXY_USER";
+/// Secret with the password of the proxy
+pub const FEED_PROXY_PASSWORD: &str = "FEED_PROXY_PASSWORD";
+/// Configuration key with the path of a PEM file with extra CA certificates, like a corporate CA
+pub const FEED_CA_BUNDLE: &str = "FEED_CA_BUNDLE";
+/// Configuration key with the connection timeout in milliseconds
+pub const FEED_CONNECT_TIMEOUT: &str = "FEED_CONNECT_TIMEOUT";
+/// Configuration key with the maximum time in milliseconds waiting for data from the server
+pub const FEED_READ_TIMEOUT: &str = "FEED_READ_TIMEOUT";
+/// Configuration key with the User-Agent header sent with every download
+pub const FEED_USER_AGENT: &str = "FEED_USER_AGENT";
+
+/// Configuration key with the number of attempts of each download. Defaults to 3.
+pub const FEED_RETRIES: &str = "FEED_RETRIES";
+/// Configuration key with the delay in milliseconds before the first retry. It doubles after each attempt.
+pub const FEED_RETRY_DELAY: &str = "FEED_RETRY_DELAY";
+
+const DEFAULT_USER_AGENT: &str = concat!("usiem-utils/", env!("CARGO_PKG_VERSION"));
+
+/// Retries of the downloads that fail because the server is unavailable
+#[derive(Clone, Debug)]
+pub struct RetryPolicy {
+    /// Total number of attempts, including the first one
+    pub attempts: u32,
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            attempts: 3,
+            base_delay: Duration::from_secs(1),
+            max_delay: Duration::from_secs(30),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Exponential backoff with jitter: a random delay between half and the full backoff of the attempt,
+    /// so the nodes of a cluster do not retry at the same time.
+    pub fn delay(&self, attempt: u32) -> Duration {
+        let backoff = self
+            .base_delay
+            .saturating_mul(2u32.saturating_pow(attempt.saturating_sub(1)))
+            .min(self.max_delay);
+        let random = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|v| v.subsec_nanos())
+            .unwrap_or_default() as f64
+            / 1_000_000_000.0;
+        backoff / 2 + backoff.mul_f64(random / 2.0)
+    }
+}
+
+/// Settings of the HTTP client used to download the feeds
+#[derive(Clone, Debug, Default)]
+pub struct HttpSettings {
+    pub proxy: Option<String>,
+    pub proxy_user: Option<String>,
+    pub proxy_password: Option<String>,
+    pub ca_bundle: Option<PathBuf>,
+    pub connect_timeout: Option<Duration>,
+    pub read_timeout: Option<Duration>,
+    pub user_agent: Option<String>,
+    pub retry: RetryPolicy,
+}
+
+impl HttpSettings {
+    /// Reads the settings from the Configuration dataset and the proxy credentials from the Secrets dataset
+    pub fn from_datasets(
+        config: Option<&TextMapSynDataset>,
+        secrets: Option<&TextMapSynDataset>,
+    ) -> Self {
+        let value = |dataset: Option<&TextMapSynDataset>, name: &'static str| {
+            dataset
+                .and_then(|v| v.get(&LogString::Borrowed(name)))
+                .map(|v| v.trim().to_string())
+                .filter(|v| !v.is_empty())
+        };
+        let millis = |name| {
+            value(config, name)
+                .and_then(|v| v.parse().ok())
+                .map(Duration::from_millis)
+        };
+        Self {
+            proxy: value(config, FEED_PROXY),
+            proxy_user: value(secrets, FEED_PROXY_USER),
+            proxy_password: value(secrets, FEED_PROXY_PASSWORD),
+            ca_bundle: value(config, FEED_CA_BUNDLE).map(PathBuf::from),
+            connect_timeout: millis(FEED_CONNECT_TIMEOUT),
+            read_timeout: millis(FEED_READ_TIMEOUT),
+            user_agent: value(config, FEED_USER_AGENT),
+            retry: RetryPolicy {
+                attempts: value(config, FEED_RETRIES)
+                    .and_then(|v| v.parse().ok())
+                    .unwrap_or(3)
+                    .max(1),
+                base_delay: millis(FEED_RETRY_DELAY).unwrap_or(Duration::from_secs(1)),
+                ..Default::default()
+            },
+        }
+    }
+
+    pub fn build_client(&self) -> TempResult<reqwest::Client> {
+        let mut builder = reqwest::Client::builder()
+            .user_agent(self.user_agent.as_deref().unwrap_or(DEFAULT_USER_AGENT));
+        if let Some(proxy) = &self.proxy {
+            let mut proxy = reqwest::Proxy::all(proxy)?;
+            if let Some(user) = &self.proxy_user {
+                proxy = proxy.basic_auth(user, self.proxy_password.as_deref().unwrap_or(""));
+            }
+            builder = builder.proxy(proxy);
+        }
+        if let Some(ca_bundle) = &self.ca_bundle {
+            let pem = std::fs::read(ca_bundle)?;
+            for certificate in reqwest::Certificate::from_pem_bundle(&pem)? {
+                builder = builder.add_root_certificate(certificate);
+            }
+        }
+        if let Some(timeout) = self.connect_timeout {
+            builder = builder.connect_timeout(timeout);
+        }
+        Ok(builder.build()?)
+    }
+}
+
+/// How the content of a feed was obtained
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FetchOrigin {
+    /// The server returned a new version
+    Downloaded,
+    /// The server answered `304 Not Modified` and the cached copy was used
+    NotModified,
+    /// The server could not be reached and the last good copy was used
+    Cached,
+    /// Read from a local folder without network access
+    Local,
+}
+
+/// Feed stored in the cache folder
+#[derive(Clone, Debug)]
+pub struct CachedFile {
+    pub path: PathBuf,
+    pub origin: FetchOrigin,
+}
+
+impl CachedFile {
+    pub async fn read(&self) -> TempResult<Vec<u8>> {
+        tokio::fs::read(&self.path).await.with_file(&self.path)
+    }
+    pub async fn read_to_string(&self) -> TempResult<String> {
+        tokio::fs::read_to_string(&self.path)
+            .await
+            .with_file(&self.path)
+    }
+}
+
+/// Source of the feed contents. The downloaders are generic over it so each feed can be
+/// loaded from fixtures in the tests.
+pub trait FeedFetcher: Send + Sync {
+    /// Obtains the feed identified by `name`, only accepting new content if `validate` does
+    fn fetch_validated<F>(
+        &self,
+        name: &str,
+        url: &str,
+        validate: F,
+    ) -> impl Future<Output = TempResult<CachedFile>> + Send
+    where
+        F: Fn(&[u8]) -> TempResult<()> + Send;
+
+    fn fetch(&self, name: &str, url: &str) -> impl Future<Output = TempResult<CachedFile>> + Send {
+        self.fetch_validated(name, url, |_| Ok(()))
+    }
+}
+
+/// Validators sent back to the server in the next conditional request
+#[derive(Serialize, Deserialize, Default, Debug)]
+struct CacheMetadata {
+    etag: Option<String>,
+    last_modified: Option<String>,
+}
+
+/// Downloads feeds into a persistent cache folder using HTTP conditional requests.
+///
+/// Each feed is identified by a name that is used as the file name inside the cache, so URLs
+/// with credentials are never written to disk.
+#[derive(Clone, Debug)]
+pub struct CachedFetcher {
+    cache_dir: PathBuf,
+    client: reqwest::Client,
+    read_timeout: Option<Duration>,
+    retry: RetryPolicy,
+    deadline: Option<Instant>,
+}
+
+impl Default for CachedFetcher {
+    fn default() -> Self {
+        Self::new(std::env::temp_dir().join("usiem_feed_cache"))
+    }
+}
+
+impl CachedFetcher {
+    pub fn new<P: Into<PathBuf>>(cache_dir: P) -> Self {
+        Self {
+            cache_dir: cache_dir.into(),
+            client: reqwest::Client::builder()
+                .user_agent(DEFAULT_USER_AGENT)
+                .build()
+                .unwrap_or_default(),
+            read_timeout: None,
+            retry: RetryPolicy::default(),
+            deadline: None,
+        }
+    }
+
+    pub fn with_settings<P: Into<PathBuf>>(
+        cache_dir: P,
+        settings: &HttpSettings,
+    ) -> TempResult<Self> {
+        Ok(Self {
+            cache_dir: cache_dir.into(),
+            client: settings.build_client()?,
+            read_timeout: settings.read_timeout,
+            retry: settings.retry.clone(),
+            deadline: None,
+        })
+    }
+
+    /// Uses the `FEED_CACHE_DIR` and HTTP settings of the Configuration dataset and the
+    /// proxy credentials of the Secrets dataset of the task
+    pub fn from_datasets(
+        config: Option<&TextMapSynDataset>,
+        secrets: Option<&TextMapSynDataset>,
+    ) -> TempResult<Self> {
+        let cache_dir = match config.and_then(|v| v.get(&LogString::Borrowed(FEED_CACHE_DIR))) {
+            Some(dir) if !dir.trim().is_empty() => PathBuf::from(dir.trim()),
+            _ => Self::default().cache_dir,
+        };
+        Self::with_settings(cache_dir, &HttpSettings::from_datasets(config, secrets))
+    }
+
+    /// No retry is started if its delay would end after the deadline, like the timeout of the task
+    pub fn with_deadline(mut self, deadline: Instant) -> Self {
+        self.deadline = Some(deadline);
+        self
+    }
+
+    pub fn cache_dir(&self) -> &Path {
+        &self.cache_dir
+    }
+
+    /// Path of the cached content of a feed
+    pub fn cache_path(&self, name: &str) -> PathBuf {
+        self.cache_dir.join(cache_file_name(name))
+    }
+
+    async fn download_with_retries(
+        &self,
+        url: &str,
+        metadata: &CacheMetadata,
+    ) -> TempResult<Option<(Vec<u8>, CacheMetadata)>> {
+        let mut attempt = 1;
+        loop {
+            match self.download(url, metadata).await {
+                Err(err) if is_unavailable(&err) && attempt < self.retry.attempts => {
+                    let delay = self.retry.delay(attempt);
+                    if let Some(deadline) = self.deadline {
+                        if Instant::now() + delay >= deadline {
+                            return Err(err);
+                        }
+                    }
+                    tokio::time::sleep(delay).await;
+                    attempt += 1;
+                }
+                res => return res,
+            }
+        }
+    }
+
+    /// Returns `None` when the server answers `304 Not Modified`
+    async fn download(
+        &self,
+        url: &str,
+        metadata: &CacheMetadata,
+    ) -> TempResult<Option<(Vec<u8>, CacheMetadata)>> {
+        let mut request = self.client.get(url);
+        if let Some(etag) = &metadata.etag {
+            request = request.header(IF_NONE_MATCH, etag);
+        }
+        if let Some(last_modified) = &metadata.last_modified {
+            request = request.header(IF_MODIFIED_SINCE, last_modified);
+        }
+        let mut response = self.with_read_timeout(request.send()).await??;
+        if response.status() == StatusCode::NOT_MODIFIED {
+            return Ok(None);
+        }
+        check_http_status(response.status())?;
+        let header = |name| {
+            response
+                .headers()
+                .get(name)
+                .and_then(|v| v.to_str().ok())
+                .map(|v| v.to_string())
+        };
+        let metadata = CacheMetadata {
+            etag: header(ETAG),
+            last_modified: header(LAST_MODIFIED),
+        };
+        // The read timeout applies to each chunk so big databases can take longer than the timeout
+        let mut content = Vec::new();
+        while let Some(chunk) = self.with_read_timeout(response.chunk()).await?? {
+            content.extend_from_slice(&chunk);
+        }
+        Ok(Some((content, metadata)))
+    }
+
+    async fn with_read_timeout<F: Future>(&self, future: F) -> TempResult<F::Output> {
+        match self.read_timeout {
+            Some(timeout) => tokio::time::timeout(timeout, future)
+                .await
+                .map_err(|_| TempErr::Timeout),
+            None => Ok(future.await),
+        }
+    }
+
+    /// Errors are returned without the feed context, which is added by `fetch_validated`
+    async fn fetch_feed<F>(&self, name: &str, url: &str, validate: F) -> TempResult<CachedFile>
+    where
+        F: Fn(&[u8]) -> TempResult<()> + Send,
+    {
+        if let Some(path) = local_path(url) {
+            let content = tokio::fs::read(&path).await.with_file(&path)?;
+            validate(&content)?;
+            return Ok(CachedFile {
+                path,
+                origin: FetchOrigin::Local,
+            });
+        }
+        let path = self.cache_path(name);
+        let meta_path = path.with_file_name(format!("{}.meta", cache_file_name(name)));
+        let cached = tokio::fs::try_exists(&path).await.unwrap_or(false);
+        let metadata: CacheMetadata = if cached {
+            match tokio::fs::read(&meta_path).await {
+                Ok(v) => usiem::serde_json::from_slice(&v).unwrap_or_default(),
+                Err(_) => CacheMetadata::default(),
+            }
+        } else {
+            CacheMetadata::default()
+        };
+        match self.download_with_retries(url, &metadata).await {
+            Ok(None) if cached => Ok(CachedFile {
+                path,
+                origin: FetchOrigin::NotModified,
+            }),
+            Ok(None) => Err(TempErr::invalid(
+                "not modified response without a cached copy",
+            )),
+            Ok(Some((content, metadata))) => {
+                validate(&content)?;
+                tokio::fs::create_dir_all(&self.cache_dir)
+                    .await
+                    .with_file(&self.cache_dir)?;
+                // Write to a temporal file first so an interrupted update never corrupts the last good copy
+                let tmp_path = path.with_file_name(format!("{}.tmp", cache_file_name(name)));
+                tokio::fs::write(&tmp_path, &content)
+                    .await
+                    .with_file(&tmp_path)?;
+                tokio::fs::rename(&tmp_path, &path).await.with_file(&path)?;
+                tokio::fs::write(&meta_path, usiem::serde_json::to_vec(&metadata)?)
+                    .await
+                    .with_file(&meta_path)?;
+                Ok(CachedFile {
+                    path,
+                    origin: FetchOrigin::Downloaded,
+                })
+            }
+            Err(err) if cached && is_unavailable(&err) => Ok(CachedFile {
+                path,
+                origin: FetchOrigin::Cached,
+            }),
+            Err(err) => Err(err),
+        }
+    }
+}
+
+impl FeedFetcher for CachedFetcher {
+    /// Downloads the feed unless the cached copy is still current.
+    ///
+    /// New content is only stored in the cache if `validate` accepts it. When the server cannot be
+    /// reached, is failing or limits the requests, the last good copy is returned instead.
+    async fn fetch_validated<F>(&self, name: &str, url: &str, validate: F) -> TempResult<CachedFile>
+    where
+        F: Fn(&[u8]) -> TempResult<()> + Send,
+    {
+        self.fetch_feed(name, url, validate)
+            .await
+            .with_feed(name, url)
+    }
+}
+
+/// Reads the feeds from a local folder using the feed name as the file name
+#[derive(Clone, Debug)]
+pub struct FileFetcher {
+    dir: PathBuf,
+}
+
+impl FileFetcher {
+    pub fn new<P: Into<PathBuf>>(dir: P) -> Self {
+        Self { dir: dir.into() }
+    }
+}
+
+impl FeedFetcher for FileFetcher {
+    async fn fetch_validated<F>(
+        &self,
+        name: &str,
+        _url: &str,
+        validate: F,
+    ) -> TempResult<CachedFile>
+    where
+        F: Fn(&[u8]) -> TempResult<()> + Send,
+    {
+        let path = self.dir.join(cache_file_name(name));
+        let content = tokio::fs::read(&path).await.with_file(&path)?;
+        validate(&content).with_file(&path)?;
+        Ok(CachedFile {
+            path,
+            origin: FetchOrigin::Local,
+        })
+    }
+}
+
+/// Returns the URL of a feed from the Configuration dataset, or the default one.
+/// Any URL can point to an internal mirror or to a local file with `file://`.
+pub fn feed_url(config: Option<&TextMapSynDataset>, key: &'static str, default: &str) -> String {
+    config
+        .and_then(|v| v.get(&LogString::Borrowed(key)))
+        .map(|v| v.trim().to_string())
+        .filter(|v| !v.is_empty())
+        .unwrap_or_else(|| default.to_string())
+}
+
+/// Path of a `file://` URL
+fn local_path(url: &str) -> Option<PathBuf> {
+    let url = reqwest::Url::parse(url).ok()?;
+    if url.scheme() != "file" {
+        return None;
+    }
+    url.to_file_path().ok()
+}
+
+/// Converts the error status codes into errors
+pub fn check_http_status(status: StatusCode) -> TempResult<()> {
+    if status.is_success() {
+        return Ok(());
+    }
+    match status.as_u16() {
+        429 => Err(TempErr::RateLimited),
+        code => Err(TempErr::HttpStatus(code)),
+    }
+}
+
+/// Errors that do not invalidate the cached copy
+fn is_unavailable(err: &TempErr) -> bool {
+    match err {
+        TempErr::Connection(_) | TempErr::RateLimited | TempErr::Timeout => true,
+        TempErr::HttpStatus(code) => *code >= 500,
+        _ => false,
+    }
+}
+
+fn cache_file_name(name: &str) -> String {
+    name.chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{BufRead, BufReader, Write},
+        net::TcpListener,
+    };
+
+    use super::*;
+    use crate::err::error_chain;
+    use crate::test_dir::TestDir;
+
+    /// Serves one response per connection and returns the headers of each request
+    fn serve(responses: Vec<String>) -> (String, std::thread::JoinHandle<Vec<String>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/feed.json", listener.local_addr().unwrap());
+        let handle = std::thread::spawn(move || {
+            let mut requests = Vec::new();
+            for response in responses {
+                let (mut stream, _) = listener.accept().unwrap();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let mut request = String::new();
+                loop {
+                    let mut line = String::new();
+                    if reader.read_line(&mut line).unwrap() == 0 || line == "\r\n" {
+                        break;
+                    }
+                    request.push_str(&line.to_lowercase());
+                }
+                stream.write_all(response.as_bytes()).unwrap();
+                requests.push(request);
+            }
+            requests
+        });
+        (url, handle)
+    }
+
+    fn response(status: &str, headers: &str, body: &str) -> String {
+        format!(
+            "HTTP/1.1 {}\r\n{}Content-Length: {}\r\nConnection: close\r\n\r\n{}",
+            status,
+            headers,
+            body.len(),
+            body
+        )
+    }
+
+    #[tokio::test]
+    async fn should_reuse_cached_feeds() {
+        let dir = TestDir::new("fetcher");
+        let settings = HttpSettings {
+            retry: RetryPolicy {
+                attempts: 1,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let fetcher = CachedFetcher::with_settings(dir.to_path_buf(), &settings).unwrap();
+        let (url, server) = serve(vec![
+            response(
+                "200 OK",
+                "ETag: \"v1\"\r\nLast-Modified: Wed, 01 May 2024 10:00:00 GMT\r\n",
+                "first",
+            ),
+            response("304 Not Modified", "", ""),
+            response("200 OK", "ETag: \"v2\"\r\n", "invalid"),
+            response("503 Service Unavailable", "", ""),
+        ]);
+
+        let file = fetcher.fetch("feed.json", &url).await.unwrap();
+        assert_eq!(FetchOrigin::Downloaded, file.origin);
+        assert_eq!("first", file.read_to_string().await.unwrap());
+
+        let file = fetcher.fetch("feed.json", &url).await.unwrap();
+        assert_eq!(FetchOrigin::NotModified, file.origin);
+        assert_eq!("first", file.read_to_string().await.unwrap());
+
+        // Rejected content must not replace the last good copy
+        let res = fetcher
+            .fetch_validated("feed.json", &url, |content| match content {
+                b"invalid" => Err(TempErr::invalid("invalid feed")),
+                _ => Ok(()),
+            })
+            .await;
+        assert!(res.is_err());
+
+        let file = fetcher.fetch("feed.json", &url).await.unwrap();
+        assert_eq!(FetchOrigin::Cached, file.origin);
+        assert_eq!("first", file.read_to_string().await.unwrap());
+
+        let requests = server.join().unwrap();
+        assert!(!requests[0].contains("if-none-match"));
+        assert!(requests[1].contains("if-none-match: \"v1\""));
+        assert!(requests[1].contains("if-modified-since: wed, 01 may 2024 10:00:00 gmt"));
+        assert!(requests[2].contains("if-none-match: \"v1\""));
+
+        // The server is down
+        let file = fetcher.fetch("feed.json", &url).await.unwrap();
+        assert_eq!(FetchOrigin::Cached, file.origin);
+        assert!(fetcher.fetch("other.json", &url).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn should_use_proxy_settings() {
+        let dir = TestDir::new("fetcher_proxy");
+        let (proxy_url, server) = serve(vec![response("200 OK", "", "proxied")]);
+        let settings = HttpSettings {
+            proxy: Some(proxy_url.trim_end_matches("/feed.json").to_string()),
+            proxy_user: Some("siem".to_string()),
+            proxy_password: Some("secret".to_string()),
+            user_agent: Some("siem-node/1.0".to_string()),
+            read_timeout: Some(Duration::from_secs(5)),
+            ..Default::default()
+        };
+        let fetcher = CachedFetcher::with_settings(dir.to_path_buf(), &settings).unwrap();
+        let file = fetcher
+            .fetch("feed.json", "http://feeds.example.com/feed.json")
+            .await
+            .unwrap();
+        assert_eq!("proxied", file.read_to_string().await.unwrap());
+        let requests = server.join().unwrap();
+        assert!(requests[0].starts_with("get http://feeds.example.com/feed.json"));
+        // siem:secret
+        assert!(requests[0].contains("proxy-authorization: basic c2llbtpzzwnyzxq="));
+        assert!(requests[0].contains("user-agent: siem-node/1.0"));
+    }
+
+    #[tokio::test]
+    async fn should_timeout_stalled_downloads() {
+        let dir = TestDir::new("fetcher_timeout");
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/feed.json", listener.local_addr().unwrap());
+        let server = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut line = String::new();
+            while reader.read_line(&mut line).unwrap() > 0 && line != "\r\n" {
+                line.clear();
+            }
+            stream
+                .write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 100\r\n\r\npartial")
+                .unwrap();
+            std::thread::sleep(Duration::from_millis(500));
+        });
+        let settings = HttpSettings {
+            read_timeout: Some(Duration::from_millis(100)),
+            retry: RetryPolicy {
+                attempts: 1,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let fetcher = CachedFetcher::with_settings(dir.to_path_buf(), &settings).unwrap();
+        let err = fetcher.fetch("feed.json", &url).await.unwrap_err();
+        assert!(matches!(err.root(), TempErr::Timeout));
+        server.join().unwrap();
+    }
+
+    #[tokio::test]
+    async fn should_retry_unavailable_servers() {
+        let dir = TestDir::new("fetcher_retry");
+        let (url, server) = serve(vec![
+            response("503 Service Unavailable", "", ""),
+            response("429 Too Many Requests", "", ""),
+            response("200 OK", "", "third"),
+            response("404 Not Found", "", ""),
+        ]);
+        let settings = HttpSettings {
+            retry: RetryPolicy {
+                attempts: 3,
+                base_delay: Duration::from_millis(10),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let fetcher = CachedFetcher::with_settings(dir.to_path_buf(), &settings).unwrap();
+        let file = fetcher.fetch("feed.json", &url).await.unwrap();
+        assert_eq!("third", file.read_to_string().await.unwrap());
+        // Client errors are not retried
+        let err = fetcher.fetch("other.json", &url).await.unwrap_err();
+        assert!(matches!(err.root(), TempErr::HttpStatus(404)));
+        assert_eq!(
+            format!("feed other.json ({}): unexpected HTTP status 404", url),
+            error_chain(&err)
+        );
+        assert_eq!(4, server.join().unwrap().len());
+
+        // No retries after the deadline
+        let fetcher = fetcher.with_deadline(Instant::now());
+        let started = Instant::now();
+        assert!(fetcher.fetch("feed2.json", &url).await.is_err());
+        assert!(started.elapsed() < Duration::from_secs(1));
+    }
+
+    #[test]
+    fn should_backoff_with_jitter() {
+        let policy = RetryPolicy {
+            attempts: 5,
+            base_delay: Duration::from_millis(100),
+            max_delay: Duration::from_millis(300),
+        };
+        for (attempt, backoff) in [(1, 100), (2, 200), (3, 300), (4, 300)] {
+            let delay = policy.delay(attempt);
+            assert!(delay >= Duration::from_millis(backoff / 2));
+            assert!(delay <= Duration::from_millis(backoff));
+        }
+    }
+}
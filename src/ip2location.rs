@@ -0,0 +1,189 @@
+use std::path::{Path, PathBuf};
+
+use usiem::{
+    prelude::{
+        geo_ip::{GeoIpDataset, GeoIpInfo},
+        SiemIp,
+    },
+    utilities::types::LogString,
+};
+
+use crate::{
+    common::ip_range_to_networks,
+    csv::{CsvReader, CsvRecord},
+    err::{ErrContext, TempErr, TempResult},
+    fetcher::FeedFetcher,
+    maxmind::{get_static_country_iso_name, get_static_country_name},
+    network::{parse_ip_range, InvalidNetwork, LoadedEntries, PrefixMode},
+};
+
+/// Name of the CSV file inside the IPv4 DB11 LITE zip
+pub const IP2LOCATION_DB11_FILE: &str = "IP2LOCATION-LITE-DB11.CSV";
+/// Name of the CSV file inside the IPv6 DB11 LITE zip. Also contains the IPv4 ranges as IPv4-mapped addresses.
+pub const IP2LOCATION_DB11_IPV6_FILE: &str = "IP2LOCATION-LITE-DB11.IPV6.CSV";
+
+/// Configuration key with the base URL of the IP2Location download service or of a mirror.
+/// With a `file://` URL the folder must contain the `DB11LITECSVIPV6.zip` or `DB11LITECSV.zip` file.
+pub const IP2LOCATION_BASE_URL: &str = "IP2LOCATION_BASE_URL";
+pub const DEFAULT_IP2LOCATION_BASE_URL: &str = "https://www.ip2location.com/download";
+
+/// Downloads the IP2Location LITE DB11 (country, region, city and coordinates) zip file.
+/// The IPv6 edition contains both IPv4 and IPv6 ranges.
+pub async fn download_ip2location_lite_db11<T: FeedFetcher>(
+    fetcher: &T,
+    base_url: &str,
+    token: &str,
+    ipv6: bool,
+) -> TempResult<PathBuf> {
+    let file_code = if ipv6 {
+        "DB11LITECSVIPV6"
+    } else {
+        "DB11LITECSV"
+    };
+    let base_url = base_url.trim_end_matches('/');
+    let database_url = if base_url.starts_with("file://") {
+        format!("{}/{}.zip", base_url, file_code)
+    } else {
+        format!("{}/?token={}&file={}", base_url, token, file_code)
+    };
+    // Errors like the download limit are returned as plain text with a 200 status
+    let file = fetcher
+        .fetch_validated(
+            &format!("IP2Location-{}.zip", file_code),
+            &database_url,
+            |content| match content.starts_with(b"PK") {
+                true => Ok(()),
+                false => Err(TempErr::invalid(
+                    "the IP2Location response is not a zip file",
+                )),
+            },
+        )
+        .await?;
+    Ok(file.path)
+}
+
+/// Inserts the ranges of an IP2Location DB11 CSV into the GeoIp dataset. Returns the number of ranges processed and
+/// of rows rejected: short rows, invalid addresses and the ranges rejected by `mode`.
+///
+/// The file has no header and the columns are `ip_from,ip_to,country_code,country_name,region_name,city_name,latitude,longitude,zip_code,time_zone`
+/// with the IPs as decimal numbers.
+pub async fn process_ip2location_db11_csv<P: AsRef<Path>>(
+    path: P,
+    mode: PrefixMode,
+    dataset: &mut GeoIpDataset,
+) -> TempResult<LoadedEntries> {
+    let path = path.as_ref();
+    let file = tokio::fs::File::open(path).await.with_file(path)?;
+    let mut reader = CsvReader::new(tokio::io::BufReader::new(file));
+    let mut values = CsvRecord::new();
+    let mut entries = LoadedEntries::default();
+    while reader.read_record(&mut values).await.with_file(path)? {
+        if values.len() < 8 {
+            let row = values.iter().collect::<Vec<_>>().join(",");
+            entries.reject(path, reader.line(), InvalidNetwork::Range(row));
+            continue;
+        }
+        let (start, end) = match (values[0].parse::<u128>(), values[1].parse::<u128>()) {
+            (Ok(start), Ok(end)) => (start, end),
+            _ => {
+                let range = format!("{}-{}", &values[0], &values[1]);
+                entries.reject(path, reader.line(), InvalidNetwork::Range(range));
+                continue;
+            }
+        };
+        // Unassigned ranges use "-" as the country
+        if &values[2] == "-" || values[2].is_empty() {
+            continue;
+        }
+        let (start, end) = if start.max(end) <= u32::MAX as u128 {
+            (SiemIp::V4(start as u32), SiemIp::V4(end as u32))
+        } else {
+            (SiemIp::V6(start), SiemIp::V6(end))
+        };
+        let (start, end) = match parse_ip_range(start, end, mode) {
+            Ok(v) => v,
+            Err(reason) => {
+                entries.reject(path, reader.line(), reason);
+                continue;
+            }
+        };
+        let info = GeoIpInfo {
+            country_iso: get_static_country_iso_name(&values[2]),
+            country: get_static_country_name(&values[3]),
+            city: match &values[5] {
+                "-" => LogString::Borrowed(""),
+                city => LogString::Owned(city.to_string()),
+            },
+            latitude: values[6].parse().unwrap_or_default(),
+            longitude: values[7].parse().unwrap_or_default(),
+            ..Default::default()
+        };
+        for (ip, net) in ip_range_to_networks(&start, &end) {
+            dataset.insert(ip, net, info.clone());
+        }
+        entries.loaded += 1;
+    }
+    Ok(entries)
+}
+
+#[cfg(all(test, not(feature = "slow_geoip")))]
+mod tests {
+    use super::*;
+    use crate::test_dir::TestDir;
+
+    #[tokio::test]
+    async fn should_process_db11_csv() {
+        let dir = TestDir::new("ip2location");
+        let path = dir.join(IP2LOCATION_DB11_IPV6_FILE);
+        tokio::fs::write(
+            &path,
+            concat!(
+                "\"0\",\"281470681743359\",\"-\",\"-\",\"-\",\"-\",\"0.000000\",\"0.000000\",\"-\",\"-\"\n",
+                "\"281470698520576\",\"281470698520831\",\"US\",\"United States of America\",\"California\",\"Los Angeles\",\"34.052230\",\"-118.243680\",\"90001\",\"-07:00\"\n",
+                "\"281470698520832\",\"281470698521599\",\"CN\",\"China\",\"Fujian\",\"Fuzhou\",\"26.061390\",\"119.306110\",\"350004\",\"+08:00\"\n",
+                "\"42540488182157650393196975867845148672\",\"42540488182158859319016590497019854847\",\"ES\",\"Spain\",\"Madrid\",\"Madrid\",\"40.416500\",\"-3.702560\",\"28001\",\"+01:00\"\n",
+                // 1.0.8.0 - 1.0.8.255 with the bounds reversed
+                "\"281470698522879\",\"281470698522624\",\"CN\",\"China\",\"Guangdong\",\"Guangzhou\",\"23.116670\",\"113.250000\",\"510000\",\"+08:00\"\n",
+                "\"1.0.9.0\",\"1.0.9.255\",\"CN\",\"China\",\"-\",\"-\",\"0\",\"0\",\"-\",\"-\"\n",
+            ),
+        )
+        .await
+        .unwrap();
+        let mut dataset = GeoIpDataset::new();
+        let entries = process_ip2location_db11_csv(&path, PrefixMode::Lenient, &mut dataset)
+            .await
+            .unwrap();
+        assert_eq!((4, 1), (entries.loaded, entries.rejected));
+        let samples: Vec<String> = entries.samples.iter().map(|v| v.to_string()).collect();
+        let expected = vec![format!(
+            "{}:6: invalid range 1.0.9.0-1.0.9.255",
+            path.display()
+        )];
+        assert_eq!(expected, samples);
+        let get = |ip: &str| dataset.get(&SiemIp::from_ip_str(ip).unwrap()).cloned();
+        let info = get("1.0.0.7").unwrap();
+        assert_eq!("US", &info.country_iso[..]);
+        assert_eq!("Los Angeles", &info.city[..]);
+        assert_eq!(34.05223, info.latitude);
+        // 1.0.1.0 - 1.0.3.255 is split into a /24 and a /23
+        assert_eq!("CN", &get("1.0.1.10").unwrap().country_iso[..]);
+        assert_eq!("CN", &get("1.0.3.200").unwrap().country_iso[..]);
+        assert_eq!("ES", &get("2001:0:4136::1").unwrap().country_iso[..]);
+        assert!(get("1.0.4.1").is_none());
+        assert_eq!("Guangzhou", &get("1.0.8.1").unwrap().city[..]);
+        let mut dataset = GeoIpDataset::new();
+        let entries = process_ip2location_db11_csv(&path, PrefixMode::Strict, &mut dataset)
+            .await
+            .unwrap();
+        assert_eq!((3, 2), (entries.loaded, entries.rejected));
+        let samples: Vec<String> = entries.samples.iter().map(|v| v.to_string()).collect();
+        let expected = vec![
+            format!(
+                "{}:5: invalid range ::ffff:1.0.8.255-::ffff:1.0.8.0",
+                path.display()
+            ),
+            format!("{}:6: invalid range 1.0.9.0-1.0.9.255", path.display()),
+        ];
+        assert_eq!(expected, samples);
+    }
+}
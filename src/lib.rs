@@ -1,22 +1,34 @@
+pub mod asn_type;
 pub mod aws;
 pub mod azure;
 pub(crate) mod common;
+pub mod csv;
+pub mod dbip;
 pub mod enrichment;
 pub mod err;
+pub mod fetcher;
+pub mod ip2location;
 pub mod maxmind;
+pub mod network;
+pub mod network_zone;
 pub mod o365;
+pub mod snapshot;
 pub mod tasks;
+#[cfg(test)]
+pub(crate) mod test_dir;
 
 #[cfg(test)]
 mod tst {
 
     use usiem::prelude::{geo_ip::GeoIpDataset, SiemIp};
 
+    use crate::fetcher::CachedFetcher;
     use crate::maxmind::{
         download_maxmind_geo_litle2_asn, download_maxmind_geo_litle2_city,
         download_maxmind_geo_litle2_country, extract_zip_db, join_path_files,
-        process_maxmind_geo_lite2_csv,
+        process_maxmind_geo_lite2_csv, MaxMindSource,
     };
+    use crate::network::PrefixMode;
 
     #[ignore]
     #[test]
@@ -45,21 +57,17 @@ mod tst {
     #[tokio::test]
     async fn should_update_geo_ip() {
         let now = std::time::Instant::now();
-        let asn_path = download_maxmind_geo_litle2_asn(
-            &std::env::var("MAXMIND_API").expect("Should exists var"),
-        )
-        .await
-        .unwrap();
-        let city_path = download_maxmind_geo_litle2_city(
-            &std::env::var("MAXMIND_API").expect("Should exists var"),
-        )
-        .await
-        .unwrap();
-        let country_path = download_maxmind_geo_litle2_country(
-            &std::env::var("MAXMIND_API").expect("Should exists var"),
-        )
-        .await
-        .unwrap();
+        let fetcher = CachedFetcher::default();
+        let source = MaxMindSource::new(std::env::var("MAXMIND_API").expect("Should exists var"));
+        let asn_path = download_maxmind_geo_litle2_asn(&fetcher, &source)
+            .await
+            .unwrap();
+        let city_path = download_maxmind_geo_litle2_city(&fetcher, &source)
+            .await
+            .unwrap();
+        let country_path = download_maxmind_geo_litle2_country(&fetcher, &source)
+            .await
+            .unwrap();
         let city_path = extract_zip_db(&city_path).await.unwrap();
         let country_path = extract_zip_db(&country_path).await.unwrap();
         let asn_path = extract_zip_db(&asn_path).await.unwrap();
@@ -71,13 +79,24 @@ mod tst {
             .unwrap();
         println!("{:?}", new_path);
         #[cfg(not(feature = "slow_geoip"))]
-        let res = process_maxmind_geo_lite2_csv("/tmp/geoip_501122574_db", true, "en")
-            .await
-            .unwrap();
+        let (res, _) = process_maxmind_geo_lite2_csv(
+            "/tmp/geoip_501122574_db",
+            true,
+            "en",
+            PrefixMode::Lenient,
+        )
+        .await
+        .unwrap();
         #[cfg(feature = "slow_geoip")]
-        let res = process_maxmind_geo_lite2_csv("/tmp/geoip_501122574_db", true, "en", "./slow_geo_ip")
-            .await
-            .unwrap();
+        let (res, _) = process_maxmind_geo_lite2_csv(
+            "/tmp/geoip_501122574_db",
+            true,
+            "en",
+            PrefixMode::Lenient,
+            "./slow_geo_ip",
+        )
+        .await
+        .unwrap();
         println!("Duration {}", now.elapsed().as_secs_f32());
         let _geoip = res.get(&SiemIp::from_ip_str("1.0.0.0").unwrap()).unwrap();
         let now = std::time::Instant::now();
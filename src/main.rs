@@ -0,0 +1,642 @@
+use std::{
+    collections::BTreeSet,
+    path::{Path, PathBuf},
+    process::ExitCode,
+    sync::Arc,
+};
+
+#[cfg(not(feature = "slow_geoip"))]
+use usiem::prelude::geo_ip::{GeoIpDataset, GeoIpInfo, GeoIpSynDataset};
+use usiem::{
+    prelude::{
+        holder::DatasetHolder,
+        ip_net::IpNetSynDataset,
+        text_map::{TextMapDataset, TextMapSynDataset},
+        LogEnrichment, SiemDataset, SiemField, SiemIp, SiemLog,
+    },
+    utilities::types::LogString,
+};
+use usiem_utils::{
+    aws::{get_aws_ips, AWS_IP_RANGES_URL, DEFAULT_AWS_IP_RANGES_URL},
+    azure::{get_azure_ips, AZURE_SERVICE_TAGS_URL, DEFAULT_AZURE_SERVICE_TAGS_URL},
+    dbip::{download_dbip_city_lite, DBIP_BASE_URL, DEFAULT_DBIP_BASE_URL},
+    enrichment::{
+        cloud_provider::CloudProviderEnricher, cloud_service::CloudServiceEnricher,
+        geoip::GeoIpEnricher,
+    },
+    err::{error_chain, task_error},
+    fetcher::{feed_url, CachedFetcher, FetchOrigin, FEED_CACHE_DIR},
+    ip2location::{
+        download_ip2location_lite_db11, DEFAULT_IP2LOCATION_BASE_URL, IP2LOCATION_BASE_URL,
+    },
+    maxmind::{
+        download_maxmind_geo_litle2_asn, download_maxmind_geo_litle2_city,
+        download_maxmind_geo_litle2_country, MaxMindSource, DEFAULT_MAXMIND_BASE_URL,
+        MAXMIND_BASE_URL,
+    },
+    network::PrefixMode,
+    o365::{get_office365_ip, DEFAULT_O365_ENDPOINTS_URL, O365_ENDPOINTS_URL},
+    snapshot::{read_snapshot_file, restore_cloud, write_cloud_snapshot, Snapshot},
+    tasks::{
+        cloud_provider::{load_aws, load_azure},
+        cloud_registry::{CloudRegistry, SanityThresholds, SourcePrefixes},
+        cloud_services::load_o365,
+    },
+};
+
+const USAGE: &str = "Build and inspect the enrichment datasets of uSIEM
+
+Usage:
+  usiem-utils fetch [--cache-dir DIR] [--config KEY=VALUE]... SOURCE...
+      Downloads the feeds into the cache. Sources: aws, azure, o365, maxmind, ip2location, dbip.
+      The credentials are read from the MAXMIND_API, MAXMIND_ACCOUNT_ID, IP2LOCATION_TOKEN,
+      FEED_PROXY_USER and FEED_PROXY_PASSWORD environment variables.
+  usiem-utils build geoip --provider maxmind|ip2location|dbip --input PATH[,PATH] [--language LANG]
+                    [--prefix-mode strict|lenient] --out DIR
+  usiem-utils build cloud [--aws FILE] [--azure FILE] [--o365 FILE] [--prefix-mode strict|lenient] --out DIR
+      Builds the snapshots from local files, without network access.
+  usiem-utils lookup IP --snapshots DIR
+      Shows the fields the enrichers would add to an IP.
+  usiem-utils diff OLD_SNAPSHOT NEW_SNAPSHOT
+      Counts the networks added, removed and changed between two snapshots.";
+
+const SECRETS: [&str; 5] = [
+    "MAXMIND_API",
+    "MAXMIND_ACCOUNT_ID",
+    "IP2LOCATION_TOKEN",
+    "FEED_PROXY_USER",
+    "FEED_PROXY_PASSWORD",
+];
+
+#[tokio::main(flavor = "current_thread")]
+async fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let res = match args.first().map(|v| &v[..]) {
+        Some("fetch") => fetch(&args[1..]).await,
+        Some("build") => build(&args[1..]).await,
+        Some("lookup") => lookup(&args[1..]).await,
+        Some("diff") => diff(&args[1..]).await,
+        Some("help" | "-h" | "--help") => {
+            println!("{}", USAGE);
+            return ExitCode::SUCCESS;
+        }
+        _ => {
+            eprintln!("{}", USAGE);
+            return ExitCode::FAILURE;
+        }
+    };
+    match res {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("Error: {}", err);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// Arguments of a subcommand. Every option has a value.
+#[derive(Debug, Default, PartialEq)]
+struct Options {
+    values: Vec<(String, String)>,
+    positional: Vec<String>,
+}
+
+impl Options {
+    fn parse(args: &[String]) -> Result<Self, String> {
+        let mut options = Options::default();
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            match arg.strip_prefix("--") {
+                Some(name) => match args.next() {
+                    Some(value) => options.values.push((name.to_string(), value.clone())),
+                    None => return Err(format!("Missing value of --{}", name)),
+                },
+                None => options.positional.push(arg.clone()),
+            }
+        }
+        Ok(options)
+    }
+
+    fn get(&self, name: &str) -> Option<&str> {
+        self.values
+            .iter()
+            .rev()
+            .find(|(k, _)| k == name)
+            .map(|(_, v)| &v[..])
+    }
+
+    fn all<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a str> {
+        self.values
+            .iter()
+            .filter(move |(k, _)| k == name)
+            .map(|(_, v)| &v[..])
+    }
+
+    fn required(&self, name: &str) -> Result<&str, String> {
+        self.get(name)
+            .ok_or_else(|| format!("Missing --{} option", name))
+    }
+}
+
+fn text_map(values: Vec<(String, String)>) -> TextMapSynDataset {
+    let mut dataset = TextMapDataset::new();
+    for (key, value) in values {
+        dataset.insert(key, value);
+    }
+    let (sender, _) = usiem::crossbeam_channel::unbounded();
+    TextMapSynDataset::new(Arc::new(dataset), sender)
+}
+
+async fn fetch(args: &[String]) -> Result<(), String> {
+    let options = Options::parse(args)?;
+    if options.positional.is_empty() {
+        return Err("No sources to fetch".to_string());
+    }
+    let mut config = Vec::new();
+    for value in options.all("config") {
+        match value.split_once('=') {
+            Some((key, value)) => config.push((key.trim().to_string(), value.trim().to_string())),
+            None => return Err(format!("Invalid --config {}, expected KEY=VALUE", value)),
+        }
+    }
+    if let Some(dir) = options.get("cache-dir") {
+        config.push((FEED_CACHE_DIR.to_string(), dir.to_string()));
+    }
+    let config = text_map(config);
+    let secrets = text_map(
+        SECRETS
+            .iter()
+            .filter_map(|name| Some((name.to_string(), std::env::var(name).ok()?)))
+            .collect(),
+    );
+    let secret = |name: &'static str| {
+        secrets
+            .get(&LogString::Borrowed(name))
+            .map(|v| v.to_string())
+    };
+    let fetcher = CachedFetcher::from_datasets(Some(&config), Some(&secrets))
+        .map_err(|err| task_error("Cannot create the HTTP client", err))?;
+    println!("Cache folder: {}", fetcher.cache_dir().display());
+    for source in &options.positional {
+        match &source.to_lowercase()[..] {
+            "aws" => {
+                let url = feed_url(Some(&config), AWS_IP_RANGES_URL, DEFAULT_AWS_IP_RANGES_URL);
+                let (ranges, origin) = get_aws_ips(&fetcher, &url)
+                    .await
+                    .map_err(|err| task_error("Cannot fetch AWS", err))?;
+                let prefixes = ranges.prefixes.len() + ranges.ipv6_prefixes.len();
+                println!("aws: {} prefixes{}", prefixes, origin_note(origin));
+            }
+            "azure" => {
+                let url = feed_url(
+                    Some(&config),
+                    AZURE_SERVICE_TAGS_URL,
+                    DEFAULT_AZURE_SERVICE_TAGS_URL,
+                );
+                let (ranges, origin) = get_azure_ips(&fetcher, &url)
+                    .await
+                    .map_err(|err| task_error("Cannot fetch Azure", err))?;
+                let prefixes: usize = ranges
+                    .values
+                    .iter()
+                    .map(|v| v.properties.address_prefixes.len())
+                    .sum();
+                println!("azure: {} prefixes{}", prefixes, origin_note(origin));
+            }
+            "o365" => {
+                let url = feed_url(
+                    Some(&config),
+                    O365_ENDPOINTS_URL,
+                    DEFAULT_O365_ENDPOINTS_URL,
+                );
+                let (services, origin) = get_office365_ip(&fetcher, &url)
+                    .await
+                    .map_err(|err| task_error("Cannot fetch O365", err))?;
+                let prefixes: usize = services.iter().map(|v| v.ips.len()).sum();
+                println!("o365: {} prefixes{}", prefixes, origin_note(origin));
+            }
+            "maxmind" => {
+                let base_url = feed_url(Some(&config), MAXMIND_BASE_URL, DEFAULT_MAXMIND_BASE_URL);
+                let license_key = match secret("MAXMIND_API") {
+                    Some(v) => v,
+                    None if base_url.starts_with("file://") => String::new(),
+                    None => return Err("Cannot find MAXMIND_API environment variable".into()),
+                };
+                let source = MaxMindSource {
+                    base_url,
+                    account_id: secret("MAXMIND_ACCOUNT_ID"),
+                    license_key,
+                };
+                for path in [
+                    download_maxmind_geo_litle2_asn(&fetcher, &source).await,
+                    download_maxmind_geo_litle2_city(&fetcher, &source).await,
+                    download_maxmind_geo_litle2_country(&fetcher, &source).await,
+                ] {
+                    let path = path.map_err(|err| task_error("Cannot fetch MaxMind", err))?;
+                    println!("maxmind: {}", path.display());
+                }
+            }
+            "ip2location" => {
+                let base_url = feed_url(
+                    Some(&config),
+                    IP2LOCATION_BASE_URL,
+                    DEFAULT_IP2LOCATION_BASE_URL,
+                );
+                let token = match secret("IP2LOCATION_TOKEN") {
+                    Some(v) => v,
+                    None if base_url.starts_with("file://") => String::new(),
+                    None => return Err("Cannot find IP2LOCATION_TOKEN environment variable".into()),
+                };
+                let path = download_ip2location_lite_db11(&fetcher, &base_url, &token, true)
+                    .await
+                    .map_err(|err| task_error("Cannot fetch IP2Location", err))?;
+                println!("ip2location: {}", path.display());
+            }
+            "dbip" | "db-ip" => {
+                let base_url = feed_url(Some(&config), DBIP_BASE_URL, DEFAULT_DBIP_BASE_URL);
+                let path = download_dbip_city_lite(&fetcher, &base_url)
+                    .await
+                    .map_err(|err| task_error("Cannot fetch DB-IP", err))?;
+                println!("dbip: {}", path.display());
+            }
+            source => return Err(format!("Unknown source {}", source)),
+        }
+    }
+    Ok(())
+}
+
+/// Tells when the server could not be reached and the last good copy was used
+fn origin_note(origin: FetchOrigin) -> &'static str {
+    match origin {
+        FetchOrigin::Cached => " (cached copy)",
+        _ => "",
+    }
+}
+
+async fn build(args: &[String]) -> Result<(), String> {
+    let options = Options::parse(args)?;
+    let out = PathBuf::from(options.required("out")?);
+    match options.positional.first().map(|v| &v[..]) {
+        Some("geoip") => build_geoip(&options, &out).await,
+        Some("cloud") => build_cloud(&options, &out).await,
+        _ => Err("Expected the dataset to build: geoip or cloud".to_string()),
+    }
+}
+
+#[cfg(not(feature = "slow_geoip"))]
+async fn build_geoip(options: &Options, out: &Path) -> Result<(), String> {
+    use usiem_utils::{
+        snapshot::write_geoip_snapshot,
+        tasks::geoip::{update_geoip, GeoIpProvider},
+    };
+
+    let paths: Vec<PathBuf> = options
+        .required("input")?
+        .split(',')
+        .map(|v| v.trim())
+        .filter(|v| !v.is_empty())
+        .map(PathBuf::from)
+        .collect();
+    let provider = match options.get("provider").unwrap_or("maxmind") {
+        "maxmind" => GeoIpProvider::MaxMind {
+            source: None,
+            language: options.get("language").unwrap_or("en").to_lowercase(),
+        },
+        "ip2location" => GeoIpProvider::Ip2Location {
+            base_url: DEFAULT_IP2LOCATION_BASE_URL.to_string(),
+            token: None,
+        },
+        "dbip" | "db-ip" => GeoIpProvider::DbIp {
+            base_url: DEFAULT_DBIP_BASE_URL.to_string(),
+        },
+        provider => return Err(format!("Unknown provider {}", provider)),
+    };
+    // Nothing is downloaded when there are local paths
+    let (dataset, entries) = update_geoip(
+        &CachedFetcher::default(),
+        provider,
+        &paths,
+        prefix_mode(options)?,
+    )
+    .await?;
+    let (data4, data6) = dataset.internal_ref();
+    let networks = data4.values().map(|v| v.len()).sum::<usize>()
+        + data6.values().map(|v| v.len()).sum::<usize>();
+    let path = write_geoip_snapshot(out, &dataset)
+        .await
+        .map_err(|err| task_error("Cannot write the snapshot", err))?;
+    println!(
+        "GeoIp: {} networks in {}, {} entries rejected",
+        networks,
+        path.display(),
+        entries.rejected
+    );
+    Ok(())
+}
+
+#[cfg(feature = "slow_geoip")]
+async fn build_geoip(_options: &Options, _out: &Path) -> Result<(), String> {
+    Err("GeoIp snapshots are not available with the slow_geoip feature".to_string())
+}
+
+fn prefix_mode(options: &Options) -> Result<PrefixMode, String> {
+    match options.get("prefix-mode") {
+        Some(name) => PrefixMode::from_name(name)
+            .ok_or_else(|| format!("Invalid --prefix-mode {}, expected strict or lenient", name)),
+        None => Ok(PrefixMode::default()),
+    }
+}
+
+async fn build_cloud(options: &Options, out: &Path) -> Result<(), String> {
+    let fetcher = CachedFetcher::default();
+    let registry = CloudRegistry::default();
+    // Every file given is accepted, there is no previous load to compare with
+    let thresholds = SanityThresholds {
+        min_prefixes: 0,
+        max_drop_percent: 100,
+    };
+    let mode = prefix_mode(options)?;
+    let mut sources = 0;
+    for source in ["aws", "azure", "o365"] {
+        let path = match options.get(source) {
+            Some(v) => v,
+            None => continue,
+        };
+        let url = file_url(Path::new(path))?;
+        let loaded = match source {
+            "aws" => load_aws(&fetcher, &url, mode).await,
+            "azure" => load_azure(&fetcher, &url, mode).await,
+            _ => load_o365(&fetcher, &url, mode).await,
+        }
+        .and_then(|prefixes| registry.replace(source, prefixes, &thresholds))
+        .map_err(|err| task_error(&format!("Cannot load {}", source), err))?;
+        println!(
+            "{}: {} prefixes, {} rejected",
+            source, loaded.prefixes, loaded.rejected
+        );
+        sources += 1;
+    }
+    if sources == 0 {
+        return Err("Expected at least one of --aws, --azure or --o365".to_string());
+    }
+    let path = write_cloud_snapshot(out, &registry)
+        .await
+        .map_err(|err| task_error("Cannot write the snapshot", err))?;
+    println!("Snapshot written to {}", path.display());
+    Ok(())
+}
+
+fn file_url(path: &Path) -> Result<String, String> {
+    let path = std::fs::canonicalize(path)
+        .map_err(|err| format!("Cannot find {}: {}", path.display(), err))?;
+    reqwest::Url::from_file_path(&path)
+        .map(|v| v.to_string())
+        .map_err(|_| format!("Invalid path {}", path.display()))
+}
+
+async fn lookup(args: &[String]) -> Result<(), String> {
+    let options = Options::parse(args)?;
+    let ip = match options.positional.first() {
+        Some(v) => SiemIp::from_ip_str(v).map_err(|_| format!("Invalid IP {}", v))?,
+        None => return Err("Expected the IP to look up".to_string()),
+    };
+    let dir = PathBuf::from(options.required("snapshots")?);
+    let fields = enrich_ip(ip, &dir).await;
+    if fields.is_empty() {
+        println!("No enrichment for {}", ip);
+    }
+    for (name, value) in fields {
+        println!("{}: {}", name, value);
+    }
+    Ok(())
+}
+
+/// Fields the enrichers add to `source.ip` using the snapshots of a folder
+async fn enrich_ip(ip: SiemIp, dir: &Path) -> Vec<(String, String)> {
+    let mut datasets = Vec::new();
+    #[cfg(not(feature = "slow_geoip"))]
+    match usiem_utils::snapshot::restore_geoip(dir).await {
+        Ok(dataset) => {
+            let (sender, _) = usiem::crossbeam_channel::unbounded();
+            datasets.push(SiemDataset::GeoIp(GeoIpSynDataset::new(
+                Arc::new(dataset),
+                sender,
+            )));
+        }
+        Err(err) => eprintln!("GeoIp snapshot not loaded: {}", error_chain(&err)),
+    }
+    match restore_cloud(dir, &CloudRegistry::default()).await {
+        Ok(cloud) => {
+            let (sender, _) = usiem::crossbeam_channel::unbounded();
+            datasets.push(SiemDataset::IpCloudProvider(IpNetSynDataset::new(
+                Arc::new(cloud.provider),
+                sender.clone(),
+            )));
+            datasets.push(SiemDataset::IpCloudService(IpNetSynDataset::new(
+                Arc::new(cloud.service),
+                sender,
+            )));
+        }
+        Err(err) => eprintln!("Cloud snapshot not loaded: {}", error_chain(&err)),
+    }
+    let datasets = DatasetHolder::from_datasets(datasets);
+    let mut log = SiemLog::new("", 0, "");
+    log.add_field("source.ip", SiemField::IP(ip));
+    let log = GeoIpEnricher::default().enrich(log, &datasets);
+    let log = CloudProviderEnricher::default().enrich(log, &datasets);
+    let log = CloudServiceEnricher::default().enrich(log, &datasets);
+    log.fields()
+        .filter(|(name, _)| name.starts_with("source.ip."))
+        .map(|(name, value)| (name.to_string(), value.to_string()))
+        .collect()
+}
+
+async fn diff(args: &[String]) -> Result<(), String> {
+    let options = Options::parse(args)?;
+    let (old, new) = match &options.positional[..] {
+        [old, new] => (Path::new(old), Path::new(new)),
+        _ => return Err("Expected the old and the new snapshot".to_string()),
+    };
+    for line in diff_snapshots(old, new).await? {
+        println!("{}", line);
+    }
+    Ok(())
+}
+
+/// One line for each cloud source, or a single line for GeoIp snapshots
+async fn diff_snapshots(old: &Path, new: &Path) -> Result<Vec<String>, String> {
+    let mut lines = Vec::new();
+    let read = |path| async move {
+        read_snapshot_file(path)
+            .await
+            .map_err(|err| task_error("Cannot read the snapshot", err))
+    };
+    match (read(old).await?, read(new).await?) {
+        (Snapshot::Cloud(old), Snapshot::Cloud(new)) => {
+            let names: BTreeSet<&String> = old.keys().chain(new.keys()).collect();
+            for name in names {
+                let empty = SourcePrefixes::default();
+                let changes = new
+                    .get(name)
+                    .unwrap_or(&empty)
+                    .changes(Some(old.get(name).unwrap_or(&empty)));
+                lines.push(format!(
+                    "{}: {} prefixes ({} added, {} removed)",
+                    name, changes.prefixes, changes.added, changes.removed
+                ));
+            }
+        }
+        #[cfg(not(feature = "slow_geoip"))]
+        (Snapshot::GeoIp(old), Snapshot::GeoIp(new)) => {
+            let (added, removed, changed) = diff_geoip(&old, &new);
+            lines.push(format!(
+                "GeoIp: {} added, {} removed, {} changed networks",
+                added, removed, changed
+            ));
+        }
+        #[allow(unreachable_patterns)]
+        _ => return Err("The snapshots contain different datasets".to_string()),
+    }
+    Ok(lines)
+}
+
+/// Networks added, removed and with different information
+#[cfg(not(feature = "slow_geoip"))]
+fn diff_geoip(old: &GeoIpDataset, new: &GeoIpDataset) -> (usize, usize, usize) {
+    fn same(a: &GeoIpInfo, b: &GeoIpInfo) -> bool {
+        a.country == b.country
+            && a.country_iso == b.country_iso
+            && a.city == b.city
+            && a.isp == b.isp
+            && a.asn == b.asn
+            && a.latitude == b.latitude
+            && a.longitude == b.longitude
+    }
+    let (old4, old6) = old.internal_ref();
+    let (new4, new6) = new.internal_ref();
+    let (mut added, mut removed, mut changed) = (0, 0, 0);
+    for (net, networks) in new4 {
+        for (ip, info) in networks {
+            match old4.get(net).and_then(|v| v.get(ip)) {
+                Some(previous) if !same(previous, info) => changed += 1,
+                Some(_) => {}
+                None => added += 1,
+            }
+        }
+    }
+    for (net, networks) in new6 {
+        for (ip, info) in networks {
+            match old6.get(net).and_then(|v| v.get(ip)) {
+                Some(previous) if !same(previous, info) => changed += 1,
+                Some(_) => {}
+                None => added += 1,
+            }
+        }
+    }
+    for (net, networks) in old4 {
+        removed += networks
+            .keys()
+            .filter(|ip| !new4.get(net).map(|v| v.contains_key(ip)).unwrap_or(false))
+            .count();
+    }
+    for (net, networks) in old6 {
+        removed += networks
+            .keys()
+            .filter(|ip| !new6.get(net).map(|v| v.contains_key(ip)).unwrap_or(false))
+            .count();
+    }
+    (added, removed, changed)
+}
+
+#[cfg(test)]
+mod test_dir;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_dir::TestDir;
+
+    #[test]
+    fn should_parse_options() {
+        let args: Vec<String> = ["cloud", "--aws", "aws.json", "--out", "/tmp/snapshots"]
+            .iter()
+            .map(|v| v.to_string())
+            .collect();
+        let options = Options::parse(&args).unwrap();
+        assert_eq!(vec!["cloud".to_string()], options.positional);
+        assert_eq!(Some("aws.json"), options.get("aws"));
+        assert_eq!(Ok("/tmp/snapshots"), options.required("out"));
+        assert!(options.required("azure").is_err());
+        assert!(Options::parse(&["--out".to_string()]).is_err());
+    }
+
+    #[tokio::test]
+    async fn should_build_look_up_and_diff_cloud_snapshots() {
+        let fixtures = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures");
+        let fixture = |name: &str| fixtures.join(name).to_string_lossy().to_string();
+        let dir = TestDir::new("cli_cloud");
+        let (old, new) = (dir.join("old"), dir.join("new"));
+        let args =
+            |values: &[&str]| -> Vec<String> { values.iter().map(|v| v.to_string()).collect() };
+        build(&args(&[
+            "cloud",
+            "--aws",
+            &fixture("aws-ip-ranges.json"),
+            "--out",
+            &old.to_string_lossy(),
+        ]))
+        .await
+        .unwrap();
+        build(&args(&[
+            "cloud",
+            "--aws",
+            &fixture("aws-ip-ranges.json"),
+            "--azure",
+            &fixture("azure-service-tags.json"),
+            "--o365",
+            &fixture("o365-endpoints.json"),
+            "--prefix-mode",
+            "strict",
+            "--out",
+            &new.to_string_lossy(),
+        ]))
+        .await
+        .unwrap();
+
+        async fn lookup(ip: &str, dir: &Path) -> Vec<(String, String)> {
+            enrich_ip(SiemIp::from_ip_str(ip).unwrap(), dir).await
+        }
+        let fields = lookup("13.69.1.1", &new).await;
+        assert!(
+            fields.iter().any(|(_, value)| value == "Azure-westeurope"),
+            "{:?}",
+            fields
+        );
+        let fields = lookup("40.97.1.1", &new).await;
+        assert!(
+            fields.iter().any(|(_, value)| value == "Exchange"),
+            "{:?}",
+            fields
+        );
+        assert!(lookup("13.69.1.1", &old).await.is_empty());
+        assert!(lookup("8.8.8.8", &new).await.is_empty());
+
+        let lines = diff_snapshots(
+            &old.join("CloudSources.snapshot"),
+            &new.join("CloudSources.snapshot"),
+        )
+        .await
+        .unwrap();
+        assert_eq!(3, lines.len(), "{:?}", lines);
+        assert_eq!("aws: 5 prefixes (0 added, 0 removed)", lines[0]);
+        assert!(
+            lines[1].starts_with("azure: 4 prefixes (4 added"),
+            "{}",
+            lines[1]
+        );
+        assert!(lines[2].starts_with("o365: "), "{}", lines[2]);
+        assert!(build(&args(&["cloud", "--out", &old.to_string_lossy()]))
+            .await
+            .is_err());
+    }
+}
@@ -0,0 +1,375 @@
+//! Parsing and validation of the networks and ranges loaded from the feeds and GeoIp databases
+use std::path::{Path, PathBuf};
+
+use usiem::prelude::SiemIp;
+
+use crate::common::network_range;
+
+/// How the networks with host bits set, like `10.1.2.3/8`, and the reversed ranges, like
+/// `10.0.0.255-10.0.0.0`, are handled
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum PrefixMode {
+    /// The network or range is rejected
+    Strict,
+    /// The host bits are cleared: `10.1.2.3/8` is loaded as `10.0.0.0/8`. The bounds of a
+    /// reversed range are swapped.
+    #[default]
+    Lenient,
+}
+
+impl PrefixMode {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match &name.trim().to_lowercase()[..] {
+            "strict" => Some(Self::Strict),
+            "lenient" => Some(Self::Lenient),
+            _ => None,
+        }
+    }
+}
+
+/// Reason why a network was rejected
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum InvalidNetwork {
+    Address(String),
+    PrefixLength(String),
+    HostBits(String),
+    Range(String),
+}
+
+impl std::fmt::Display for InvalidNetwork {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Address(text) => write!(f, "invalid address in {}", text),
+            Self::PrefixLength(text) => write!(f, "invalid prefix length in {}", text),
+            Self::HostBits(text) => write!(f, "host bits set in {}", text),
+            Self::Range(text) => write!(f, "invalid range {}", text),
+        }
+    }
+}
+
+/// Parses a network like `10.0.0.0/8` or `2001:db8::/32`.
+///
+/// A bare IP is a /32 or /128 network. IPv4-mapped IPv6 networks (`::ffff:10.0.0.0/104`) are
+/// returned as IPv4 and the zone of link-local addresses (`fe80::1%eth0`) is ignored.
+pub fn parse_network(text: &str, mode: PrefixMode) -> Result<(SiemIp, u8), InvalidNetwork> {
+    let text = text.trim();
+    let (address, net) = match text.split_once('/') {
+        Some((address, net)) => (address, Some(net)),
+        None => (text, None),
+    };
+    let address = address_without_zone(address);
+    let (ip, bits) = if address.contains(':') {
+        match address.parse::<std::net::Ipv6Addr>() {
+            Ok(ip) => (u128::from(ip), 128),
+            Err(_) => return Err(InvalidNetwork::Address(text.to_string())),
+        }
+    } else {
+        match address.parse::<std::net::Ipv4Addr>() {
+            Ok(ip) => (u32::from(ip) as u128, 32),
+            Err(_) => return Err(InvalidNetwork::Address(text.to_string())),
+        }
+    };
+    let net = match net {
+        Some(prefix) => match prefix.parse::<u8>() {
+            Ok(net) if net as u32 <= bits && prefix.bytes().all(|v| v.is_ascii_digit()) => net,
+            _ => return Err(InvalidNetwork::PrefixLength(text.to_string())),
+        },
+        None => bits as u8,
+    };
+    let (network, _) = network_range(ip, net, bits);
+    if network != ip && mode == PrefixMode::Strict {
+        return Err(InvalidNetwork::HostBits(text.to_string()));
+    }
+    Ok(match bits {
+        32 => (SiemIp::V4(network as u32), net),
+        _ if network >> 32 == 0xffff && net >= 96 => (SiemIp::V4(network as u32), net - 96),
+        _ => (SiemIp::V6(network), net),
+    })
+}
+
+fn address_without_zone(address: &str) -> &str {
+    match address.split_once('%') {
+        Some((address, _)) if address.contains(':') => address,
+        _ => address,
+    }
+}
+
+/// Parses a network in lenient mode
+pub fn parse_ip_network(text: &str) -> Option<(SiemIp, u8)> {
+    parse_network(text, PrefixMode::Lenient).ok()
+}
+
+/// Checks the range `start..=end` of a GeoIp database. Ranges mixing IPv4 and IPv6 addresses are
+/// always rejected.
+pub fn parse_ip_range(
+    start: SiemIp,
+    end: SiemIp,
+    mode: PrefixMode,
+) -> Result<(SiemIp, SiemIp), InvalidNetwork> {
+    let reversed = match (&start, &end) {
+        (SiemIp::V4(start), SiemIp::V4(end)) => start > end,
+        (SiemIp::V6(start), SiemIp::V6(end)) => start > end,
+        _ => return Err(InvalidNetwork::Range(format!("{}-{}", start, end))),
+    };
+    match (reversed, mode) {
+        (false, _) => Ok((start, end)),
+        (true, PrefixMode::Lenient) => Ok((end, start)),
+        (true, PrefixMode::Strict) => Err(InvalidNetwork::Range(format!("{}-{}", start, end))),
+    }
+}
+
+/// Number of rejected entries kept with their reason in `LoadedEntries`
+pub const MAX_REJECTED_SAMPLES: usize = 5;
+
+/// Entry rejected while loading a database or a list of networks
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RejectedEntry {
+    /// File and line of the entry, when they are known
+    pub location: Option<(PathBuf, usize)>,
+    pub reason: InvalidNetwork,
+}
+
+impl std::fmt::Display for RejectedEntry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.location {
+            Some((path, line)) => write!(f, "{}:{}: {}", path.display(), line, self.reason),
+            None => write!(f, "{}", self.reason),
+        }
+    }
+}
+
+/// Entries of a database that were loaded and rejected
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct LoadedEntries {
+    pub loaded: usize,
+    pub rejected: usize,
+    /// The first rejected entries, up to `MAX_REJECTED_SAMPLES`
+    pub samples: Vec<RejectedEntry>,
+}
+
+impl LoadedEntries {
+    /// Counts a rejected entry of a file
+    pub fn reject(&mut self, path: &Path, line: usize, reason: InvalidNetwork) {
+        self.reject_entry(RejectedEntry {
+            location: Some((path.to_path_buf(), line)),
+            reason,
+        });
+    }
+
+    pub fn reject_entry(&mut self, entry: RejectedEntry) {
+        self.rejected += 1;
+        if self.samples.len() < MAX_REJECTED_SAMPLES {
+            self.samples.push(entry);
+        }
+    }
+
+    /// The rejected samples separated by commas, ending with "..." when some were not kept
+    pub fn samples_text(&self) -> String {
+        let mut samples: Vec<String> = self.samples.iter().map(|v| v.to_string()).collect();
+        if self.rejected > samples.len() {
+            samples.push("...".to_string());
+        }
+        samples.join(", ")
+    }
+
+    /// Adds the entries loaded from another file
+    pub fn merge(&mut self, other: LoadedEntries) {
+        self.loaded += other.loaded;
+        self.rejected += other.rejected;
+        let free = MAX_REJECTED_SAMPLES.saturating_sub(self.samples.len());
+        self.samples.extend(other.samples.into_iter().take(free));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use proptest::prelude::*;
+
+    use super::*;
+
+    #[test]
+    fn should_reject_invalid_networks() {
+        for text in [
+            "10.0.0.0/",
+            "10.0.0.0/-1",
+            "10.0.0.0/+8",
+            "10.0.0.0/ 8",
+            "::/",
+        ] {
+            assert!(
+                matches!(
+                    parse_network(text, PrefixMode::Lenient),
+                    Err(InvalidNetwork::PrefixLength(_))
+                ),
+                "{}",
+                text
+            );
+        }
+        for text in ["/8", "10.0.0/8", "10.0.0.256/32", "fe80::g/64", ""] {
+            assert!(
+                matches!(
+                    parse_network(text, PrefixMode::Lenient),
+                    Err(InvalidNetwork::Address(_))
+                ),
+                "{}",
+                text
+            );
+        }
+        assert_eq!(
+            "host bits set in 10.1.2.3/8",
+            parse_network("10.1.2.3/8", PrefixMode::Strict)
+                .unwrap_err()
+                .to_string()
+        );
+    }
+
+    #[test]
+    fn should_normalize_networks() {
+        let parse = |text: &str| parse_network(text, PrefixMode::Lenient).unwrap();
+        assert_eq!((SiemIp::V4(0x0a00_0000), 8), parse("10.1.2.3/8"));
+        assert_eq!((SiemIp::V4(0x0a01_0203), 32), parse(" 10.1.2.3 "));
+        assert_eq!((SiemIp::V6(1), 128), parse("::1"));
+        assert_eq!((SiemIp::V4(0x0a00_0000), 8), parse("::ffff:10.0.0.0/104"));
+        assert_eq!((SiemIp::V4(0x0a00_0001), 32), parse("::ffff:a00:1"));
+        // Shorter prefixes include other addresses and stay as IPv6
+        assert_eq!((SiemIp::V6(0), 80), parse("::ffff:0:0/80"));
+        let link_local = 0xfe80_u128 << 112 | 1;
+        assert_eq!((SiemIp::V6(link_local), 128), parse("fe80::1%eth0"));
+        assert_eq!(
+            (SiemIp::V6(0xfe80_u128 << 112), 64),
+            parse("fe80::1%eth0/64")
+        );
+        assert_eq!(
+            (SiemIp::V6(0xfe80_u128 << 112), 64),
+            parse_network("fe80::%2/64", PrefixMode::Strict).unwrap()
+        );
+    }
+
+    #[test]
+    fn should_keep_the_first_rejected_entries() {
+        let path = Path::new("blocks.csv");
+        let mut entries = LoadedEntries::default();
+        for line in 2..10 {
+            entries.reject(path, line, InvalidNetwork::Address(line.to_string()));
+        }
+        let mut other = LoadedEntries {
+            loaded: 3,
+            ..Default::default()
+        };
+        other.reject_entry(RejectedEntry {
+            location: None,
+            reason: InvalidNetwork::HostBits("10.1.2.3/8".into()),
+        });
+        assert_eq!("host bits set in 10.1.2.3/8", other.samples[0].to_string());
+        entries.merge(other);
+        assert_eq!((3, 9), (entries.loaded, entries.rejected));
+        assert_eq!(MAX_REJECTED_SAMPLES, entries.samples.len());
+        assert_eq!(
+            "blocks.csv:2: invalid address in 2",
+            entries.samples[0].to_string()
+        );
+    }
+
+    #[test]
+    fn should_check_ip_ranges() {
+        let (low, high) = (SiemIp::V4(0x0a00_0000), SiemIp::V4(0x0a00_00ff));
+        for mode in [PrefixMode::Lenient, PrefixMode::Strict] {
+            assert_eq!(Ok((low, high)), parse_ip_range(low, high, mode));
+            assert_eq!(Ok((low, low)), parse_ip_range(low, low, mode));
+            assert!(matches!(
+                parse_ip_range(low, SiemIp::V6(0xff), mode),
+                Err(InvalidNetwork::Range(_))
+            ));
+        }
+        assert_eq!(
+            Ok((low, high)),
+            parse_ip_range(high, low, PrefixMode::Lenient)
+        );
+        assert_eq!(
+            "invalid range 10.0.0.255-10.0.0.0",
+            parse_ip_range(high, low, PrefixMode::Strict)
+                .unwrap_err()
+                .to_string()
+        );
+    }
+
+    const PIECES: [&str; 16] = [
+        "0", "1", "9", "255", "256", "f", "F", "g", ".", ":", "::", "/", "/32", " ", "-", "ñ",
+    ];
+
+    /// IPv6 addresses outside of the IPv4-mapped range, that are returned as IPv4
+    fn ipv6() -> impl Strategy<Value = u128> {
+        any::<u128>().prop_map(|ip| ip | 1 << 127)
+    }
+
+    proptest! {
+        #[test]
+        fn should_not_panic_with_random_networks(
+            pieces in proptest::collection::vec(proptest::sample::select(&PIECES[..]), 0..24),
+        ) {
+            let text = pieces.concat();
+            for mode in [PrefixMode::Lenient, PrefixMode::Strict] {
+                if let Ok((ip, net)) = parse_network(&text, mode) {
+                    match ip {
+                        SiemIp::V4(_) => prop_assert!(net <= 32, "{}", text),
+                        SiemIp::V6(_) => prop_assert!(net <= 128, "{}", text),
+                    }
+                }
+            }
+        }
+
+        #[test]
+        fn should_not_panic_with_any_text(text in any::<String>()) {
+            let _ = parse_network(&text, PrefixMode::Strict);
+            let _ = parse_ip_network(&text);
+        }
+
+        #[test]
+        fn should_round_trip_ipv4_networks(ip in any::<u32>(), net in 0..=32u8) {
+            let (network, _) = network_range(ip as u128, net, 32);
+            let text = format!("{}/{}", SiemIp::V4(ip), net);
+            let masked = Ok((SiemIp::V4(network as u32), net));
+            prop_assert_eq!(&masked, &parse_network(&text, PrefixMode::Lenient));
+            if network != ip as u128 {
+                prop_assert_eq!(
+                    Err(InvalidNetwork::HostBits(text.clone())),
+                    parse_network(&text, PrefixMode::Strict)
+                );
+            }
+            let text = format!("{}/{}", SiemIp::V4(network as u32), net);
+            prop_assert_eq!(&masked, &parse_network(&text, PrefixMode::Strict));
+        }
+
+        #[test]
+        fn should_round_trip_ipv6_networks(ip in ipv6(), net in 0..=128u8) {
+            let (network, _) = network_range(ip, net, 128);
+            let text = format!("{}/{}", SiemIp::V6(ip), net);
+            let masked = Ok((SiemIp::V6(network), net));
+            prop_assert_eq!(&masked, &parse_network(&text, PrefixMode::Lenient));
+            let text = format!("{}/{}", SiemIp::V6(network), net);
+            prop_assert_eq!(&masked, &parse_network(&text, PrefixMode::Strict));
+        }
+
+        #[test]
+        fn should_reject_long_ipv4_prefixes(ip in any::<u32>(), net in 33..=300u32) {
+            let text = format!("{}/{}", SiemIp::V4(ip), net);
+            for mode in [PrefixMode::Lenient, PrefixMode::Strict] {
+                prop_assert_eq!(
+                    Err(InvalidNetwork::PrefixLength(text.clone())),
+                    parse_network(&text, mode)
+                );
+            }
+        }
+
+        #[test]
+        fn should_reject_long_ipv6_prefixes(ip in ipv6(), net in 129..=300u32) {
+            let text = format!("{}/{}", SiemIp::V6(ip), net);
+            for mode in [PrefixMode::Lenient, PrefixMode::Strict] {
+                prop_assert_eq!(
+                    Err(InvalidNetwork::PrefixLength(text.clone())),
+                    parse_network(&text, mode)
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,411 @@
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+use usiem::{
+    prelude::{ip_net::IpNetDataset, SiemIp},
+    utilities::types::LogString,
+};
+
+use crate::{
+    common::{network_range, range_to_cidrs},
+    csv::{CsvReader, CsvRecord},
+    err::{ErrContext, TempErr, TempResult},
+    network::{parse_network, LoadedEntries, PrefixMode, RejectedEntry},
+};
+
+/// Name of the CustomMapIpNet dataset that maps a network to its zone (DMZ, OT, PCI...)
+pub const NETWORK_ZONE_DATASET: &str = "NetworkZone";
+/// Name of the CustomMapIpNet dataset that maps a network to its VLAN
+pub const NETWORK_VLAN_DATASET: &str = "NetworkVlan";
+/// Name of the CustomMapIpNet dataset that maps a network to its site
+pub const NETWORK_SITE_DATASET: &str = "NetworkSite";
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct NetworkZoneRecord {
+    pub network: String,
+    pub zone: String,
+    #[serde(default)]
+    pub vlan: String,
+    #[serde(default)]
+    pub site: String,
+}
+
+/// Datasets built from a list of zone records
+#[derive(Default)]
+pub struct NetworkZoneDatasets {
+    pub zone: IpNetDataset,
+    pub vlan: IpNetDataset,
+    pub site: IpNetDataset,
+    /// Records loaded and records rejected because the network is not valid
+    pub entries: LoadedEntries,
+}
+
+/// Reads the zone records from a CSV file (with a `network,zone,vlan,site` header) or from a YAML list, depending on the file extension.
+pub async fn read_network_zones_file<P: AsRef<Path>>(
+    path: P,
+) -> TempResult<Vec<NetworkZoneRecord>> {
+    let extension = path
+        .as_ref()
+        .extension()
+        .map(|v| v.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+    match &extension[..] {
+        "yaml" | "yml" => read_network_zones_yaml(path).await,
+        "csv" => read_network_zones_csv(path).await,
+        _ => Err(TempErr::file(
+            path,
+            TempErr::invalid("network zone files must be CSV or YAML"),
+        )),
+    }
+}
+
+pub async fn read_network_zones_yaml<P: AsRef<Path>>(
+    path: P,
+) -> TempResult<Vec<NetworkZoneRecord>> {
+    let path = path.as_ref();
+    let content = tokio::fs::read_to_string(path).await.with_file(path)?;
+    serde_yaml::from_str(&content).map_err(|err| TempErr::Parse {
+        path: path.to_path_buf(),
+        line: err.location().map(|v| v.line()).unwrap_or(1),
+        message: format!("invalid network zone YAML: {}", err),
+    })
+}
+
+pub async fn read_network_zones_csv<P: AsRef<Path>>(path: P) -> TempResult<Vec<NetworkZoneRecord>> {
+    let path = path.as_ref();
+    let file = tokio::fs::File::open(path).await.with_file(path)?;
+    let mut reader = CsvReader::new(tokio::io::BufReader::new(file));
+    let parse_error = |line: usize, message: &str| TempErr::Parse {
+        path: path.to_path_buf(),
+        line,
+        message: message.to_string(),
+    };
+    let header = reader
+        .read_header()
+        .await
+        .with_file(path)?
+        .ok_or_else(|| parse_error(1, "empty network zone file"))?;
+    if header.index("network").is_none() {
+        return Err(parse_error(
+            reader.line(),
+            "the header has no network column",
+        ));
+    }
+    let column = |record: &CsvRecord, name: &str| {
+        header
+            .index(name)
+            .and_then(|pos| record.get(pos))
+            .map(|v| v.trim().to_string())
+            .unwrap_or_default()
+    };
+    let mut values = CsvRecord::new();
+    let mut records = Vec::with_capacity(128);
+    while reader.read_record(&mut values).await.with_file(path)? {
+        if values.len() == 1 && values[0].trim().is_empty() {
+            continue;
+        }
+        records.push(NetworkZoneRecord {
+            network: column(&values, "network"),
+            zone: column(&values, "zone"),
+            vlan: column(&values, "vlan"),
+            site: column(&values, "site"),
+        });
+    }
+    Ok(records)
+}
+
+/// Builds the zone, VLAN and site datasets. Each attribute is resolved independently using the record with the longest prefix that defines it.
+/// Records with an invalid network are skipped and counted as rejected.
+pub fn build_network_zone_datasets(records: &[NetworkZoneRecord]) -> NetworkZoneDatasets {
+    let mut entries = LoadedEntries::default();
+    let mut networks = Vec::with_capacity(records.len());
+    for (pos, record) in records.iter().enumerate() {
+        match parse_network(&record.network, PrefixMode::Lenient) {
+            Ok((ip, net)) => {
+                entries.loaded += 1;
+                networks.push((ip, net, pos));
+            }
+            Err(reason) => entries.reject_entry(RejectedEntry {
+                location: None,
+                reason,
+            }),
+        }
+    }
+    NetworkZoneDatasets {
+        zone: build_ip_net_dataset(records, &networks, |v| &v.zone),
+        vlan: build_ip_net_dataset(records, &networks, |v| &v.vlan),
+        site: build_ip_net_dataset(records, &networks, |v| &v.site),
+        entries,
+    }
+}
+
+fn build_ip_net_dataset(
+    records: &[NetworkZoneRecord],
+    networks: &[(SiemIp, u8, usize)],
+    attribute: fn(&NetworkZoneRecord) -> &String,
+) -> IpNetDataset {
+    let mut ranges4 = Vec::with_capacity(networks.len());
+    let mut ranges6 = Vec::with_capacity(networks.len());
+    for (ip, net, pos) in networks {
+        if attribute(&records[*pos]).is_empty() {
+            continue;
+        }
+        match ip {
+            SiemIp::V4(ip) => {
+                let (start, end) = network_range(*ip as u128, *net, 32);
+                ranges4.push((start, end, *net, *pos));
+            }
+            SiemIp::V6(ip) => {
+                let (start, end) = network_range(*ip, *net, 128);
+                ranges6.push((start, end, *net, *pos));
+            }
+        }
+    }
+    let mut dataset = IpNetDataset::new();
+    for (ip, net, pos) in longest_prefix_blocks(&ranges4, 32) {
+        dataset.insert(
+            SiemIp::V4(ip as u32),
+            net,
+            LogString::Owned(attribute(&records[pos]).clone()),
+        );
+    }
+    for (ip, net, pos) in longest_prefix_blocks(&ranges6, 128) {
+        dataset.insert(
+            SiemIp::V6(ip),
+            net,
+            LogString::Owned(attribute(&records[pos]).clone()),
+        );
+    }
+    dataset
+}
+
+/// Converts a list of possibly nested networks `(start, end, prefix, record)` into non overlapping CIDR blocks assigned to the most specific record.
+///
+/// The networks are swept in order with a stack of the ones that contain the current address:
+/// networks never overlap partially, so the top of the stack is always the most specific one.
+fn longest_prefix_blocks(ranges: &[(u128, u128, u8, usize)], bits: u32) -> Vec<(u128, u8, usize)> {
+    let mut sorted: Vec<(u128, u128, usize)> = ranges
+        .iter()
+        .map(|(start, end, _, record)| (*start, *end, *record))
+        .collect();
+    // Containing networks first. With the same network the last record wins.
+    sorted.sort_unstable_by(|a, b| a.0.cmp(&b.0).then(b.1.cmp(&a.1)).then(a.2.cmp(&b.2)));
+    let mut segments: Vec<(u128, u128, usize)> = Vec::with_capacity(sorted.len());
+    let mut open: Vec<(u128, usize)> = Vec::new();
+    // First address not assigned yet, None after the last address
+    let mut from = Some(0u128);
+    for (start, end, record) in sorted {
+        while let Some((open_end, open_record)) = open.last().copied() {
+            if open_end >= start {
+                break;
+            }
+            from = push_segment(&mut segments, from, open_end, open_record);
+            open.pop();
+        }
+        if let Some((_, open_record)) = open.last().copied() {
+            if start > 0 {
+                push_segment(&mut segments, from, start - 1, open_record);
+            }
+        }
+        from = Some(start);
+        open.push((end, record));
+    }
+    while let Some((open_end, open_record)) = open.pop() {
+        from = push_segment(&mut segments, from, open_end, open_record);
+    }
+    let mut blocks = Vec::with_capacity(segments.len());
+    for (start, end, record) in segments {
+        for (ip, net) in range_to_cidrs(start, end, bits) {
+            blocks.push((ip, net, record));
+        }
+    }
+    blocks
+}
+
+/// Assigns `from..=end` to the record, joining it with the previous segment of the same record.
+/// Returns the address after `end`.
+fn push_segment(
+    segments: &mut Vec<(u128, u128, usize)>,
+    from: Option<u128>,
+    end: u128,
+    record: usize,
+) -> Option<u128> {
+    let start = match from {
+        Some(v) if v <= end => v,
+        _ => return from,
+    };
+    match segments.last_mut() {
+        Some(last) if last.2 == record && last.1.checked_add(1) == Some(start) => last.1 = end,
+        _ => segments.push((start, end, record)),
+    }
+    end.checked_add(1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_dir::TestDir;
+
+    fn record(network: &str, zone: &str, vlan: &str, site: &str) -> NetworkZoneRecord {
+        NetworkZoneRecord {
+            network: network.into(),
+            zone: zone.into(),
+            vlan: vlan.into(),
+            site: site.into(),
+        }
+    }
+
+    #[test]
+    fn should_use_the_longest_prefix() {
+        let datasets = build_network_zone_datasets(&[
+            record("10.0.0.0/8", "Corporate", "", "Madrid"),
+            record("10.40.0.0/16", "PCI-VLAN-40", "40", ""),
+            record("10.40.5.0/24", "DMZ", "", ""),
+            record("fd00::/8", "OT", "200", "Plant"),
+            record("10.50.0.0/33", "Lab", "", ""),
+        ]);
+        let get = |dataset: &IpNetDataset, ip: &str| {
+            dataset
+                .get(&SiemIp::from_ip_str(ip).unwrap())
+                .map(|v| v.to_string())
+        };
+        assert_eq!(Some("Corporate".into()), get(&datasets.zone, "10.1.2.3"));
+        assert_eq!(Some("PCI-VLAN-40".into()), get(&datasets.zone, "10.40.1.1"));
+        assert_eq!(Some("DMZ".into()), get(&datasets.zone, "10.40.5.7"));
+        assert_eq!(Some("PCI-VLAN-40".into()), get(&datasets.zone, "10.40.6.1"));
+        assert_eq!(Some("Corporate".into()), get(&datasets.zone, "10.41.0.1"));
+        assert_eq!(None, get(&datasets.zone, "11.0.0.1"));
+        assert_eq!(Some("40".into()), get(&datasets.vlan, "10.40.5.7"));
+        assert_eq!(None, get(&datasets.vlan, "10.1.2.3"));
+        assert_eq!(Some("Madrid".into()), get(&datasets.site, "10.40.5.7"));
+        assert_eq!(Some("Madrid".into()), get(&datasets.site, "10.1.2.3"));
+        assert_eq!(Some("OT".into()), get(&datasets.zone, "fd00::1"));
+        assert_eq!(Some("Plant".into()), get(&datasets.site, "fd00::1"));
+        assert_eq!(
+            None,
+            get(&datasets.zone, "10.50.0.1").filter(|v| v == "Lab")
+        );
+        assert_eq!((4, 1), (datasets.entries.loaded, datasets.entries.rejected));
+        assert_eq!(
+            "invalid prefix length in 10.50.0.0/33",
+            datasets.entries.samples[0].to_string()
+        );
+    }
+
+    /// Scan of every range for each boundary, the previous implementation
+    fn reference_blocks(ranges: &[(u128, u128, u8, usize)], bits: u32) -> Vec<(u128, u8, usize)> {
+        let mut points: Vec<u128> = Vec::with_capacity(ranges.len() * 2);
+        for (start, end, _, _) in ranges {
+            points.push(*start);
+            if let Some(next) = end.checked_add(1) {
+                points.push(next);
+            }
+        }
+        points.sort_unstable();
+        points.dedup();
+        let mut segments: Vec<(u128, u128, usize)> = Vec::new();
+        for (pos, start) in points.iter().enumerate() {
+            let end = match points.get(pos + 1) {
+                Some(next) => next - 1,
+                None => u128::MAX,
+            };
+            let best = ranges
+                .iter()
+                .filter(|(r_start, r_end, _, _)| r_start <= start && *r_end >= end)
+                .max_by_key(|(_, _, net, record)| (*net, *record));
+            if let Some((_, _, _, record)) = best {
+                push_segment(&mut segments, Some(*start), end, *record);
+            }
+        }
+        segments
+            .into_iter()
+            .flat_map(|(start, end, record)| {
+                range_to_cidrs(start, end, bits)
+                    .into_iter()
+                    .map(move |(ip, net)| (ip, net, record))
+            })
+            .collect()
+    }
+
+    proptest::proptest! {
+        #[test]
+        fn should_match_the_reference_blocks(
+            networks in proptest::collection::vec((0u32..64, 26u8..=32), 0..24),
+        ) {
+            // Small networks of a /26 so they are often nested or repeated
+            let ranges: Vec<(u128, u128, u8, usize)> = networks
+                .iter()
+                .enumerate()
+                .map(|(record, (ip, net))| {
+                    let (start, end) = network_range(0x0a00_0000 | *ip as u128, *net, 32);
+                    (start, end, *net, record)
+                })
+                .collect();
+            proptest::prop_assert_eq!(
+                reference_blocks(&ranges, 32),
+                longest_prefix_blocks(&ranges, 32)
+            );
+        }
+    }
+
+    #[test]
+    fn should_cover_the_whole_address_space() {
+        let ranges = [
+            (0, u128::MAX, 0, 0),
+            (u128::MAX - 255, u128::MAX, 120, 1),
+            (0, 255, 120, 2),
+        ];
+        assert_eq!(
+            reference_blocks(&ranges, 128),
+            longest_prefix_blocks(&ranges, 128)
+        );
+    }
+
+    #[tokio::test]
+    async fn should_read_csv_and_yaml_files() {
+        let dir = TestDir::new("network_zone");
+        let csv_path = dir.join("zones.csv");
+        tokio::fs::write(
+            &csv_path,
+            "network,zone,vlan,site\n10.0.0.0/8,Corporate,,Madrid\n\"10.40.0.0/16\",\"PCI, VLAN 40\",40,\n",
+        )
+        .await
+        .unwrap();
+        let yaml_path = dir.join("zones.yaml");
+        tokio::fs::write(
+            &yaml_path,
+            "- network: 10.0.0.0/8\n  zone: Corporate\n  site: Madrid\n- network: 10.40.0.0/16\n  zone: PCI, VLAN 40\n  vlan: \"40\"\n",
+        )
+        .await
+        .unwrap();
+        let expected = vec![
+            record("10.0.0.0/8", "Corporate", "", "Madrid"),
+            record("10.40.0.0/16", "PCI, VLAN 40", "40", ""),
+        ];
+        assert_eq!(expected, read_network_zones_file(&csv_path).await.unwrap());
+        assert_eq!(expected, read_network_zones_file(&yaml_path).await.unwrap());
+
+        // Invalid files report the line with the error
+        tokio::fs::write(
+            &yaml_path,
+            "- network: 10.0.0.0/8\n  zone: Corporate\n- network: [10.40.0.0/16]\n  zone: PCI\n",
+        )
+        .await
+        .unwrap();
+        match read_network_zones_file(&yaml_path).await {
+            Err(TempErr::Parse { path, line, .. }) => {
+                assert_eq!(yaml_path, path);
+                assert_eq!(3, line);
+            }
+            res => panic!("Unexpected result {:?}", res),
+        }
+        tokio::fs::write(&csv_path, "net,zone\n10.0.0.0/8,Corporate\n")
+            .await
+            .unwrap();
+        let err = read_network_zones_file(&csv_path).await.unwrap_err();
+        assert_eq!(
+            format!("{}:1: the header has no network column", csv_path.display()),
+            err.to_string()
+        );
+    }
+}
@@ -3,15 +3,27 @@ use usiem::{
     utilities::types::LogString,
 };
 
-use crate::err::TempResult;
+use crate::{
+    err::TempResult,
+    fetcher::{FeedFetcher, FetchOrigin},
+};
+
+/// Configuration key to download the Office 365 endpoints from another URL
+pub const O365_ENDPOINTS_URL: &str = "O365_ENDPOINTS_URL";
+pub const DEFAULT_O365_ENDPOINTS_URL: &str = "https://endpoints.office.com/endpoints/worldwide?clientrequestid=b10c5ed1-bad1-445f-b386-b919946339a7";
 
-pub async fn get_office365_ip() -> TempResult<Vec<Office365ServiceInfo>> {
-    let body = reqwest::get("https://endpoints.office.com/endpoints/worldwide?clientrequestid=b10c5ed1-bad1-445f-b386-b919946339a7")
-    .await?
-    .text()
-    .await?;
-    let res:  Vec<Office365ServiceInfo> = usiem::serde_json::from_str(&body)?;
-    Ok(res)
+/// Downloads the Office 365 endpoints, returning also how the feed was obtained
+pub async fn get_office365_ip<T: FeedFetcher>(
+    fetcher: &T,
+    url: &str,
+) -> TempResult<(Vec<Office365ServiceInfo>, FetchOrigin)> {
+    let file = fetcher
+        .fetch_validated("o365-endpoints.json", url, |content| {
+            Ok(usiem::serde_json::from_slice::<Vec<Office365ServiceInfo>>(content).map(|_| ())?)
+        })
+        .await?;
+    let res: Vec<Office365ServiceInfo> = usiem::serde_json::from_slice(&file.read().await?)?;
+    Ok((res, file.origin))
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
@@ -47,5 +59,16 @@ pub fn static_service(service: &str) -> LogString {
 
 #[tokio::test]
 async fn test_o365() {
-    let _res = get_office365_ip().await;
+    let fetcher =
+        crate::fetcher::FileFetcher::new(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures"));
+    let (res, origin) = get_office365_ip(&fetcher, DEFAULT_O365_ENDPOINTS_URL)
+        .await
+        .unwrap();
+    assert_eq!(FetchOrigin::Local, origin);
+    assert_eq!(3, res.len());
+    assert_eq!("Exchange", res[0].service_area);
+    assert_eq!(3, res[0].ips.len());
+    // Services without IPs only publish URLs
+    assert!(res[2].ips.is_empty());
+    assert_eq!("O365 Common", &static_service(&res[1].service_area)[..]);
 }
@@ -0,0 +1,583 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    io::{Read, Write},
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+#[cfg(not(feature = "slow_geoip"))]
+use usiem::prelude::geo_ip::{GeoIpDataset, GeoIpInfo};
+use usiem::{
+    prelude::{ip_net::IpNetDataset, text_map::TextMapSynDataset, SiemIp},
+    utilities::types::LogString,
+};
+
+use crate::{
+    err::{ErrContext, TempErr, TempResult},
+    tasks::cloud_registry::{CloudRegistry, SourcePrefixes},
+};
+
+/// Configuration key with the folder of the snapshots. No snapshots are written if it is not set.
+pub const SNAPSHOT_DIR: &str = "SNAPSHOT_DIR";
+/// Version of the snapshot format. Snapshots with other versions are rejected.
+///
+/// A snapshot starts with the `USIEMSNP` magic, this version (u16) and the kind of dataset (u8),
+/// followed by the gzip compressed records. Strings are stored once in a table and referenced by
+/// index. Numbers are little endian.
+pub const SNAPSHOT_VERSION: u16 = 1;
+
+const MAGIC: &[u8; 8] = b"USIEMSNP";
+#[cfg(not(feature = "slow_geoip"))]
+const GEOIP_KIND: u8 = 1;
+const CLOUD_KIND: u8 = 2;
+#[cfg(not(feature = "slow_geoip"))]
+const GEOIP_FILE: &str = "GeoIp.snapshot";
+const CLOUD_FILE: &str = "CloudSources.snapshot";
+
+/// IpCloudProvider and IpCloudService datasets restored from a snapshot
+#[derive(Default)]
+pub struct CloudDatasets {
+    pub provider: IpNetDataset,
+    pub service: IpNetDataset,
+}
+
+/// Contents of a snapshot file
+pub enum Snapshot {
+    #[cfg(not(feature = "slow_geoip"))]
+    GeoIp(GeoIpDataset),
+    /// Accepted prefixes of each cloud source
+    Cloud(BTreeMap<String, SourcePrefixes>),
+}
+
+/// Snapshot folder from the Configuration dataset
+pub fn snapshot_dir(config: Option<&TextMapSynDataset>) -> Option<PathBuf> {
+    config
+        .and_then(|v| v.get(&LogString::Borrowed(SNAPSHOT_DIR)))
+        .map(|v| v.trim())
+        .filter(|v| !v.is_empty())
+        .map(PathBuf::from)
+}
+
+/// Writes the GeoIp dataset built by the GeoIp task
+#[cfg(not(feature = "slow_geoip"))]
+pub async fn write_geoip_snapshot(dir: &Path, dataset: &GeoIpDataset) -> TempResult<PathBuf> {
+    let content = encode_geoip(dataset)?;
+    write_snapshot(dir, GEOIP_FILE, &content).await
+}
+
+/// Restores the GeoIp dataset written by the GeoIp task
+#[cfg(not(feature = "slow_geoip"))]
+pub async fn restore_geoip(dir: &Path) -> TempResult<GeoIpDataset> {
+    let path = dir.join(GEOIP_FILE);
+    let content = read_snapshot(&path, Some(GEOIP_KIND)).await?.1;
+    decode_geoip(&content).with_file(&path)
+}
+
+/// Writes the last accepted prefixes of every cloud source
+pub async fn write_cloud_snapshot(dir: &Path, registry: &CloudRegistry) -> TempResult<PathBuf> {
+    let content = encode_cloud(&registry.export())?;
+    write_snapshot(dir, CLOUD_FILE, &content).await
+}
+
+/// Restores the cloud sources into the registry and returns the datasets built from them
+pub async fn restore_cloud(dir: &Path, registry: &CloudRegistry) -> TempResult<CloudDatasets> {
+    let path = dir.join(CLOUD_FILE);
+    let content = read_snapshot(&path, Some(CLOUD_KIND)).await?.1;
+    registry.restore(decode_cloud(&content).with_file(&path)?);
+    Ok(CloudDatasets {
+        provider: registry.build_provider(),
+        service: registry.build_service(),
+    })
+}
+
+/// Reads a snapshot of any dataset
+pub async fn read_snapshot_file(path: &Path) -> TempResult<Snapshot> {
+    let (kind, content) = read_snapshot(path, None).await?;
+    match kind {
+        #[cfg(not(feature = "slow_geoip"))]
+        GEOIP_KIND => Ok(Snapshot::GeoIp(decode_geoip(&content).with_file(path)?)),
+        CLOUD_KIND => Ok(Snapshot::Cloud(decode_cloud(&content).with_file(path)?)),
+        kind => Err(TempErr::file(
+            path,
+            TempErr::invalid(format!("unsupported snapshot dataset ({})", kind)),
+        )),
+    }
+}
+
+async fn write_snapshot(dir: &Path, file_name: &str, content: &[u8]) -> TempResult<PathBuf> {
+    tokio::fs::create_dir_all(dir).await.with_file(dir)?;
+    let path = dir.join(file_name);
+    // Both cloud tasks write the same snapshot, each one uses its own temporal file
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .subsec_nanos();
+    let tmp_path = dir.join(format!("{}.{}.tmp", file_name, nanos));
+    tokio::fs::write(&tmp_path, content)
+        .await
+        .with_file(&tmp_path)?;
+    tokio::fs::rename(&tmp_path, &path).await.with_file(&path)?;
+    Ok(path)
+}
+
+/// Checks the header and returns the kind of dataset and the uncompressed records
+async fn read_snapshot(path: &Path, kind: Option<u8>) -> TempResult<(u8, Vec<u8>)> {
+    let content = tokio::fs::read(path).await.with_file(path)?;
+    let invalid = |message: String| TempErr::file(path, TempErr::Invalid(message));
+    if content.len() < 11 || &content[..8] != MAGIC {
+        return Err(invalid("not a snapshot file".to_string()));
+    }
+    let version = u16::from_le_bytes([content[8], content[9]]);
+    if version != SNAPSHOT_VERSION {
+        return Err(invalid(format!(
+            "snapshot version {} is not supported, expected {}",
+            version, SNAPSHOT_VERSION
+        )));
+    }
+    if kind.map(|kind| kind != content[10]).unwrap_or(false) {
+        return Err(invalid(format!(
+            "the snapshot contains another dataset ({})",
+            content[10]
+        )));
+    }
+    let mut records = Vec::with_capacity(content.len() * 4);
+    flate2::read::GzDecoder::new(&content[11..])
+        .read_to_end(&mut records)
+        .with_file(path)?;
+    Ok((content[10], records))
+}
+
+fn compress(kind: u8, records: &[u8]) -> TempResult<Vec<u8>> {
+    let mut content = Vec::with_capacity(records.len() / 4);
+    content.extend_from_slice(MAGIC);
+    content.extend_from_slice(&SNAPSHOT_VERSION.to_le_bytes());
+    content.push(kind);
+    let mut encoder = flate2::write::GzEncoder::new(content, flate2::Compression::default());
+    encoder.write_all(records)?;
+    Ok(encoder.finish()?)
+}
+
+#[cfg(not(feature = "slow_geoip"))]
+fn encode_geoip(dataset: &GeoIpDataset) -> TempResult<Vec<u8>> {
+    let (data4, data6) = dataset.internal_ref();
+    let mut strings = StringTable::default();
+    let mut records = Encoder::default();
+    let v4 = data4.values().map(|v| v.len()).sum::<usize>();
+    records.u32(v4 as u32);
+    for (net, networks) in data4 {
+        for (ip, info) in networks {
+            records.u32(*ip);
+            records.u8(*net as u8);
+            encode_geoip_info(&mut records, &mut strings, info);
+        }
+    }
+    let v6 = data6.values().map(|v| v.len()).sum::<usize>();
+    records.u32(v6 as u32);
+    for (net, networks) in data6 {
+        for (ip, info) in networks {
+            records.u128(*ip);
+            records.u8(*net as u8);
+            encode_geoip_info(&mut records, &mut strings, info);
+        }
+    }
+    compress(GEOIP_KIND, &strings.prepend(records))
+}
+
+#[cfg(not(feature = "slow_geoip"))]
+fn encode_geoip_info(records: &mut Encoder, strings: &mut StringTable, info: &GeoIpInfo) {
+    records.u32(strings.index(&info.country));
+    records.u32(strings.index(&info.country_iso));
+    records.u32(strings.index(&info.city));
+    records.u32(strings.index(&info.isp));
+    records.f32(info.latitude);
+    records.f32(info.longitude);
+    records.u32(info.asn);
+}
+
+#[cfg(not(feature = "slow_geoip"))]
+fn decode_geoip(content: &[u8]) -> TempResult<GeoIpDataset> {
+    let mut decoder = Decoder::new(content);
+    let strings = decoder.strings()?;
+    let mut dataset = GeoIpDataset::new();
+    for v6 in [false, true] {
+        for _ in 0..decoder.u32()? {
+            let ip = match v6 {
+                false => SiemIp::V4(decoder.u32()?),
+                true => SiemIp::V6(decoder.u128()?),
+            };
+            let net = decoder.prefix(&ip)?;
+            let info = GeoIpInfo {
+                country: decoder.string(&strings)?,
+                country_iso: decoder.string(&strings)?,
+                city: decoder.string(&strings)?,
+                isp: decoder.string(&strings)?,
+                latitude: decoder.f32()?,
+                longitude: decoder.f32()?,
+                asn: decoder.u32()?,
+            };
+            dataset.insert(ip, net, info);
+        }
+    }
+    Ok(dataset)
+}
+
+fn encode_cloud(sources: &BTreeMap<String, SourcePrefixes>) -> TempResult<Vec<u8>> {
+    let mut strings = StringTable::default();
+    let mut records = Encoder::default();
+    records.u32(sources.len() as u32);
+    for (name, source) in sources {
+        records.u32(strings.index(name));
+        records.u32(source.prefixes as u32);
+        for networks in [&source.provider, &source.service] {
+            records.u32(networks.len() as u32);
+            for (ip, net, value) in networks {
+                match ip {
+                    SiemIp::V4(ip) => {
+                        records.u8(4);
+                        records.u32(*ip);
+                    }
+                    SiemIp::V6(ip) => {
+                        records.u8(6);
+                        records.u128(*ip);
+                    }
+                }
+                records.u8(*net);
+                records.u32(strings.index(value));
+            }
+        }
+    }
+    compress(CLOUD_KIND, &strings.prepend(records))
+}
+
+fn decode_cloud(content: &[u8]) -> TempResult<BTreeMap<String, SourcePrefixes>> {
+    let mut decoder = Decoder::new(content);
+    let strings = decoder.strings()?;
+    let mut sources = BTreeMap::new();
+    for _ in 0..decoder.u32()? {
+        let name = decoder.string(&strings)?.to_string();
+        let mut source = SourcePrefixes {
+            prefixes: decoder.u32()? as usize,
+            ..Default::default()
+        };
+        for networks in [&mut source.provider, &mut source.service] {
+            for _ in 0..decoder.u32()? {
+                let ip = match decoder.u8()? {
+                    4 => SiemIp::V4(decoder.u32()?),
+                    6 => SiemIp::V6(decoder.u128()?),
+                    family => {
+                        return Err(TempErr::invalid(format!(
+                            "invalid IP family {} in snapshot",
+                            family
+                        )))
+                    }
+                };
+                networks.push((ip, decoder.prefix(&ip)?, decoder.string(&strings)?));
+            }
+        }
+        sources.insert(name, source);
+    }
+    Ok(sources)
+}
+
+/// Distinct strings of a snapshot, in order of appearance
+#[derive(Default)]
+struct StringTable {
+    positions: HashMap<String, u32>,
+    strings: Vec<String>,
+}
+
+impl StringTable {
+    fn index(&mut self, value: &str) -> u32 {
+        if let Some(pos) = self.positions.get(value) {
+            return *pos;
+        }
+        let pos = self.strings.len() as u32;
+        self.strings.push(value.to_string());
+        self.positions.insert(value.to_string(), pos);
+        pos
+    }
+
+    /// The table goes before the records that reference it
+    fn prepend(self, records: Encoder) -> Vec<u8> {
+        let mut content = Encoder::default();
+        content.u32(self.strings.len() as u32);
+        for value in &self.strings {
+            content.u32(value.len() as u32);
+            content.0.extend_from_slice(value.as_bytes());
+        }
+        content.0.extend_from_slice(&records.0);
+        content.0
+    }
+}
+
+#[derive(Default)]
+struct Encoder(Vec<u8>);
+
+impl Encoder {
+    fn u8(&mut self, value: u8) {
+        self.0.push(value);
+    }
+    fn u32(&mut self, value: u32) {
+        self.0.extend_from_slice(&value.to_le_bytes());
+    }
+    fn u128(&mut self, value: u128) {
+        self.0.extend_from_slice(&value.to_le_bytes());
+    }
+    #[cfg(not(feature = "slow_geoip"))]
+    fn f32(&mut self, value: f32) {
+        self.0.extend_from_slice(&value.to_le_bytes());
+    }
+}
+
+struct Decoder<'a> {
+    content: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Decoder<'a> {
+    fn new(content: &'a [u8]) -> Self {
+        Self { content, pos: 0 }
+    }
+
+    fn bytes<const N: usize>(&mut self) -> TempResult<[u8; N]> {
+        let bytes = self
+            .content
+            .get(self.pos..self.pos + N)
+            .ok_or_else(|| TempErr::invalid("truncated snapshot"))?;
+        self.pos += N;
+        let mut value = [0; N];
+        value.copy_from_slice(bytes);
+        Ok(value)
+    }
+
+    fn u8(&mut self) -> TempResult<u8> {
+        Ok(self.bytes::<1>()?[0])
+    }
+    fn u32(&mut self) -> TempResult<u32> {
+        Ok(u32::from_le_bytes(self.bytes()?))
+    }
+    /// Prefix length of a network, that cannot be longer than the address
+    fn prefix(&mut self, ip: &SiemIp) -> TempResult<u8> {
+        let net = self.u8()?;
+        let bits = match ip {
+            SiemIp::V4(_) => 32,
+            SiemIp::V6(_) => 128,
+        };
+        if net > bits {
+            return Err(TempErr::invalid("invalid prefix length in snapshot"));
+        }
+        Ok(net)
+    }
+    fn u128(&mut self) -> TempResult<u128> {
+        Ok(u128::from_le_bytes(self.bytes()?))
+    }
+    #[cfg(not(feature = "slow_geoip"))]
+    fn f32(&mut self) -> TempResult<f32> {
+        Ok(f32::from_le_bytes(self.bytes()?))
+    }
+
+    fn strings(&mut self) -> TempResult<Vec<LogString>> {
+        let count = self.u32()? as usize;
+        let mut strings = Vec::with_capacity(count.min(self.content.len()));
+        for _ in 0..count {
+            let len = self.u32()? as usize;
+            let bytes = self
+                .content
+                .get(self.pos..self.pos + len)
+                .ok_or_else(|| TempErr::invalid("truncated snapshot"))?;
+            self.pos += len;
+            let value = std::str::from_utf8(bytes)
+                .map_err(|_| TempErr::invalid("invalid UTF-8 string in snapshot"))?;
+            strings.push(LogString::Owned(value.to_string()));
+        }
+        Ok(strings)
+    }
+
+    fn string(&mut self, strings: &[LogString]) -> TempResult<LogString> {
+        let pos = self.u32()? as usize;
+        strings
+            .get(pos)
+            .cloned()
+            .ok_or_else(|| TempErr::invalid("invalid string reference in snapshot"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_dir::TestDir;
+
+    fn get(dataset: &IpNetDataset, ip: &str) -> Option<String> {
+        dataset
+            .get(&SiemIp::from_ip_str(ip).unwrap())
+            .map(|v| v.to_string())
+    }
+
+    #[tokio::test]
+    async fn should_restore_cloud_snapshots() {
+        let dir = TestDir::new("cloud_snapshot");
+        let registry = CloudRegistry::default();
+        let aws = SourcePrefixes {
+            prefixes: 2,
+            rejected: 0,
+            cached: false,
+            provider: vec![
+                (
+                    SiemIp::from_ip_str("18.209.0.0").unwrap(),
+                    16,
+                    LogString::Borrowed("AWS-us-east-1"),
+                ),
+                (
+                    SiemIp::from_ip_str("2600:1f18::").unwrap(),
+                    33,
+                    LogString::Borrowed("AWS-us-east-1"),
+                ),
+            ],
+            service: vec![(
+                SiemIp::from_ip_str("18.209.0.0").unwrap(),
+                16,
+                LogString::Borrowed("EC2"),
+            )],
+        };
+        let thresholds = crate::tasks::cloud_registry::SanityThresholds {
+            min_prefixes: 1,
+            ..Default::default()
+        };
+        registry.replace("aws", aws, &thresholds).unwrap();
+        write_cloud_snapshot(&dir, &registry).await.unwrap();
+
+        let restored = CloudRegistry::default();
+        let datasets = restore_cloud(&dir, &restored).await.unwrap();
+        assert_eq!(Some(2), restored.prefixes("aws"));
+        assert_eq!(
+            Some("AWS-us-east-1"),
+            get(&datasets.provider, "18.209.1.1").as_deref()
+        );
+        assert_eq!(
+            Some("AWS-us-east-1"),
+            get(&datasets.provider, "2600:1f18::1").as_deref()
+        );
+        assert_eq!(Some("EC2"), get(&datasets.service, "18.209.1.1").as_deref());
+
+        // Other versions and corrupted files are rejected
+        let path = dir.join(CLOUD_FILE);
+        let mut content = tokio::fs::read(&path).await.unwrap();
+        content[8] = 99;
+        tokio::fs::write(&path, &content).await.unwrap();
+        let err = restore_cloud(&dir, &CloudRegistry::default())
+            .await
+            .err()
+            .unwrap();
+        assert_eq!(
+            format!(
+                "file {}: snapshot version 99 is not supported, expected 1",
+                path.display()
+            ),
+            crate::err::error_chain(&err)
+        );
+        content[8] = 1;
+        content.truncate(content.len() - 10);
+        tokio::fs::write(&path, &content).await.unwrap();
+        assert!(restore_cloud(&dir, &CloudRegistry::default())
+            .await
+            .is_err());
+    }
+
+    #[cfg(not(feature = "slow_geoip"))]
+    #[tokio::test]
+    async fn should_restore_geoip_snapshots() {
+        let dir = TestDir::new("geoip_snapshot");
+        let info = |city: &'static str, asn| GeoIpInfo {
+            country: LogString::Borrowed("Spain"),
+            country_iso: LogString::Borrowed("ES"),
+            city: LogString::Borrowed(city),
+            latitude: 40.4,
+            longitude: -3.7,
+            isp: LogString::Borrowed("Telefonica"),
+            asn,
+        };
+        let mut dataset = GeoIpDataset::new();
+        dataset.insert(
+            SiemIp::from_ip_str("80.58.0.0").unwrap(),
+            16,
+            info("Madrid", 3352),
+        );
+        dataset.insert(
+            SiemIp::from_ip_str("80.59.0.0").unwrap(),
+            16,
+            info("Sevilla", 3352),
+        );
+        dataset.insert(
+            SiemIp::from_ip_str("2a02:9000::").unwrap(),
+            23,
+            info("Madrid", 3352),
+        );
+        write_geoip_snapshot(&dir, &dataset).await.unwrap();
+        let restored = restore_geoip(&dir).await.unwrap();
+        let city = |ip: &str| {
+            restored
+                .get(&SiemIp::from_ip_str(ip).unwrap())
+                .map(|v| (v.city.to_string(), v.asn, v.latitude))
+        };
+        assert_eq!(Some(("Madrid".to_string(), 3352, 40.4)), city("80.58.1.1"));
+        assert_eq!(Some(("Sevilla".to_string(), 3352, 40.4)), city("80.59.1.1"));
+        assert_eq!(
+            Some(("Madrid".to_string(), 3352, 40.4)),
+            city("2a02:9000::1")
+        );
+        assert_eq!(None, city("8.8.8.8"));
+        // A cloud snapshot is not a GeoIp snapshot
+        write_cloud_snapshot(&dir, &CloudRegistry::default())
+            .await
+            .unwrap();
+        tokio::fs::rename(dir.join(CLOUD_FILE), dir.join(GEOIP_FILE))
+            .await
+            .unwrap();
+        assert!(restore_geoip(&dir).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn should_reject_invalid_prefix_lengths() {
+        let dir = TestDir::new("invalid_snapshot");
+        for (family, ip, net) in [(4, 0x0a00_0000u128, 33), (6, 0x2001_0db8 << 96, 129)] {
+            let mut strings = StringTable::default();
+            let mut records = Encoder::default();
+            records.u32(1);
+            records.u32(strings.index("aws"));
+            records.u32(1);
+            records.u32(1);
+            records.u8(family);
+            match family {
+                4 => records.u32(ip as u32),
+                _ => records.u128(ip),
+            }
+            records.u8(net);
+            records.u32(strings.index("AWS-us-east-1"));
+            records.u32(0);
+            let content = compress(CLOUD_KIND, &strings.prepend(records)).unwrap();
+            write_snapshot(&dir, CLOUD_FILE, &content).await.unwrap();
+            let err = match restore_cloud(&dir, &CloudRegistry::default()).await {
+                Ok(_) => panic!("the /{} prefix must be rejected", net),
+                Err(err) => crate::err::error_chain(&err),
+            };
+            assert!(err.contains("invalid prefix length in snapshot"), "{}", err);
+        }
+        #[cfg(not(feature = "slow_geoip"))]
+        {
+            let mut strings = StringTable::default();
+            let mut records = Encoder::default();
+            records.u32(1);
+            records.u32(0x0a00_0000);
+            records.u8(40);
+            encode_geoip_info(&mut records, &mut strings, &GeoIpInfo::default());
+            records.u32(0);
+            let content = compress(GEOIP_KIND, &strings.prepend(records)).unwrap();
+            write_snapshot(&dir, GEOIP_FILE, &content).await.unwrap();
+            let err = match restore_geoip(&dir).await {
+                Ok(_) => panic!("the /40 prefix must be rejected"),
+                Err(err) => crate::err::error_chain(&err),
+            };
+            assert!(err.contains("invalid prefix length in snapshot"), "{}", err);
+        }
+    }
+}
@@ -0,0 +1,100 @@
+use std::collections::BTreeMap;
+
+use usiem::{
+    prelude::{
+        task::{SiemTaskData, SiemTaskResult, TaskDefinition, TaskFireMode},
+        text_map::TextMapSynDataset,
+        try_to_custom_map_text, SiemDatasetType, SiemError,
+    },
+    utilities::types::LogString,
+};
+
+use crate::{
+    asn_type::{
+        build_asn_type_dataset, parse_asn_type_sources, read_asn_type_file, ASN_TYPE_DATASET,
+    },
+    err::task_error,
+};
+
+/// Configuration key with the comma separated list of ASN files. Each entry is a path or `type=path`.
+pub const ASN_TYPE_FILES: &str = "ASN_TYPE_FILES";
+
+pub fn asn_type_definition() -> TaskDefinition {
+    TaskDefinition::new(
+        SiemTaskData::OTHER(LogString::Borrowed("UPDATE_ASN_TYPE"), BTreeMap::new()),
+        LogString::Borrowed("AsnType"),
+        LogString::Borrowed(
+            "Update the ASN type dataset (hosting, vpn, isp...) from local CSV lists",
+        ),
+        usiem::prelude::UserRole::Administrator,
+        TaskFireMode::Repetitive(86_400_000),
+        60_000,
+        |task, datasets| {
+            let config: TextMapSynDataset = match datasets.get(&SiemDatasetType::Configuration) {
+                Some(v) => match v.clone().try_into() {
+                    Ok(v) => v,
+                    Err(_) => {
+                        return Err(SiemError::Task(
+                            "Configuration is not supported by this SIEM implementation"
+                                .to_string(),
+                        ))
+                    }
+                },
+                None => {
+                    return Err(SiemError::Task(
+                        "Configuration is not supported by this SIEM implementation".to_string(),
+                    ))
+                }
+            };
+            let sources = match config.get(&LogString::Borrowed(ASN_TYPE_FILES)) {
+                Some(v) => v.to_string(),
+                None => {
+                    return Err(SiemError::Task(
+                        "ASN_TYPE_FILES configuration is not setted, cannot update dataset"
+                            .to_string(),
+                    ))
+                }
+            };
+            let asn_type: TextMapSynDataset = match datasets.get(&SiemDatasetType::CustomMapText(
+                LogString::Borrowed(ASN_TYPE_DATASET),
+            )) {
+                Some(v) => match try_to_custom_map_text(v.clone(), ASN_TYPE_DATASET) {
+                    Ok(v) => v,
+                    Err(_) => {
+                        return Err(SiemError::Task(
+                            "AsnType dataset is not supported by this SIEM implementation"
+                                .to_string(),
+                        ))
+                    }
+                },
+                None => {
+                    return Err(SiemError::Task(
+                        "AsnType dataset is not supported by this SIEM implementation".to_string(),
+                    ))
+                }
+            };
+
+            Ok(Box::pin(async move {
+                let mut entries = Vec::with_capacity(4096);
+                for (default_type, path) in parse_asn_type_sources(&sources) {
+                    match read_asn_type_file(path, default_type).await {
+                        Ok(v) => entries.extend(v),
+                        Err(err) => {
+                            return SiemTaskResult {
+                                data: Some(Err(task_error("Cannot read ASN type file", err))),
+                                id: task.id,
+                            }
+                        }
+                    }
+                }
+                let dataset = build_asn_type_dataset(&entries);
+                let total = dataset.internal_ref().len();
+                asn_type.update(dataset);
+                SiemTaskResult {
+                    data: Some(Ok(format!("Correctly updated AsnType with {} ASNs", total))),
+                    id: task.id,
+                }
+            }))
+        },
+    )
+}
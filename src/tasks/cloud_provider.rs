@@ -1,18 +1,33 @@
+use std::time::{Duration, Instant};
+
 use usiem::{
     prelude::{
         ip_net::IpNetSynDataset,
         task::{SiemTaskData, SiemTaskResult, TaskDefinition, TaskFireMode},
-        SiemDatasetType, SiemError, SiemIp,
+        text_map::TextMapSynDataset,
+        SiemDatasetType, SiemError,
     },
     utilities::types::LogString,
 };
 
 use crate::{
-    aws::{get_aws_ips, static_region, static_service},
-    azure::{self, get_azure_ips},
-    common::{parse_ip4_network, parse_ip6_network},
+    aws::{
+        get_aws_ips, static_region, static_service, AWS_IP_RANGES_URL, DEFAULT_AWS_IP_RANGES_URL,
+    },
+    azure::{self, get_azure_ips, AZURE_SERVICE_TAGS_URL, DEFAULT_AZURE_SERVICE_TAGS_URL},
+    err::{task_error, TempErr},
+    fetcher::{feed_url, CachedFetcher, FeedFetcher, FetchOrigin},
+    network::PrefixMode,
+    snapshot::{restore_cloud, snapshot_dir, write_cloud_snapshot},
 };
 
+use super::{
+    cloud_registry::{prefix_mode, CloudRegistry, SanityThresholds, SourcePrefixes},
+    report::{is_source_disabled, SourceStatus, UpdateReport},
+};
+
+const CLOUD_PROVIDER_TIMEOUT: u64 = 600_000;
+
 pub fn cloud_provider_definition() -> TaskDefinition {
     TaskDefinition::new(
         SiemTaskData::UPDATE_CLOUD_PROVIDER,
@@ -20,141 +35,283 @@ pub fn cloud_provider_definition() -> TaskDefinition {
         LogString::Borrowed("Update cloud provider dataset with AWS and Azure"),
         usiem::prelude::UserRole::Administrator,
         TaskFireMode::Repetitive(86400000),
-        600_000,
+        CLOUD_PROVIDER_TIMEOUT,
         |task, datasets| {
             let cloud_service: IpNetSynDataset =
                 match datasets.get(&SiemDatasetType::IpCloudService) {
                     Some(v) => match v.clone().try_into() {
                         Ok(v) => v,
-                        Err(_) => {
-                            return Err(SiemError::Task(format!(
-                        "Cloud service dataset is not supported by this SIEM implementation"
-                    )))
-                        }
+                        Err(_) => return Err(SiemError::Task(
+                            "Cloud service dataset is not supported by this SIEM implementation"
+                                .to_string(),
+                        )),
                     },
                     None => {
-                        return Err(SiemError::Task(format!(
+                        return Err(SiemError::Task(
                             "Cloud service dataset is not supported by this SIEM implementation"
-                        )))
+                                .to_string(),
+                        ))
                     }
                 };
             let cloud_provider: IpNetSynDataset =
                 match datasets.get(&SiemDatasetType::IpCloudProvider) {
                     Some(v) => match v.clone().try_into() {
                         Ok(v) => v,
-                        Err(_) => {
-                            return Err(SiemError::Task(format!(
-                        "Cloud provider dataset is not supported by this SIEM implementation"
-                    )))
-                        }
+                        Err(_) => return Err(SiemError::Task(
+                            "Cloud provider dataset is not supported by this SIEM implementation"
+                                .to_string(),
+                        )),
                     },
                     None => {
-                        return Err(SiemError::Task(format!(
+                        return Err(SiemError::Task(
                             "Cloud provider dataset is not supported by this SIEM implementation"
-                        )))
+                                .to_string(),
+                        ))
                     }
                 };
+            let config: Option<TextMapSynDataset> = datasets
+                .get(&SiemDatasetType::Configuration)
+                .and_then(|v| v.clone().try_into().ok());
+            let secrets: Option<TextMapSynDataset> = datasets
+                .get(&SiemDatasetType::Secrets(LogString::Borrowed(
+                    "CloudProvider",
+                )))
+                .and_then(|v| v.clone().try_into().ok());
+            let fetcher = match CachedFetcher::from_datasets(config.as_ref(), secrets.as_ref()) {
+                Ok(v) => v,
+                Err(err) => {
+                    return Err(SiemError::Task(task_error(
+                        "Cannot create the HTTP client, check FEED_PROXY and FEED_CA_BUNDLE",
+                        err,
+                    )))
+                }
+            };
+
+            let aws_disabled = is_source_disabled(config.as_ref(), "aws");
+            let azure_disabled = is_source_disabled(config.as_ref(), "azure");
+            let aws_url = feed_url(
+                config.as_ref(),
+                AWS_IP_RANGES_URL,
+                DEFAULT_AWS_IP_RANGES_URL,
+            );
+            let azure_url = feed_url(
+                config.as_ref(),
+                AZURE_SERVICE_TAGS_URL,
+                DEFAULT_AZURE_SERVICE_TAGS_URL,
+            );
+
+            let registry = CloudRegistry::global();
+            let thresholds = SanityThresholds::from_config(config.as_ref());
+            let mode = prefix_mode(config.as_ref());
+            let snapshot_dir = snapshot_dir(config.as_ref());
 
             Ok(Box::pin(async move {
-                match get_aws_ips().await {
-                    Ok(aws_ranges) => {
-                        for service in aws_ranges.prefixes {
-                            if let Some((ip, net)) = parse_ip4_network(&service.ip_prefix) {
-                                if !service.region.is_empty() {
-                                    cloud_provider.insert(
-                                        SiemIp::V4(ip),
-                                        net,
-                                        static_region(service.region),
-                                    );
-                                }
-                                if service.service != "AMAZON" && !service.service.is_empty() {
-                                    cloud_service.insert(
-                                        SiemIp::V4(ip),
-                                        net,
-                                        static_service(service.service),
-                                    );
-                                }
-                            }
-                        }
-                        for service in aws_ranges.ipv6_prefixes {
-                            if let Some((ip, net)) = parse_ip6_network(&service.ipv6_prefix) {
-                                if !service.region.is_empty() {
-                                    cloud_provider.insert(
-                                        SiemIp::V6(ip),
-                                        net,
-                                        static_region(service.region),
-                                    );
-                                }
-                                if service.service != "AMAZON" && !service.service.is_empty() {
-                                    cloud_service.insert(
-                                        SiemIp::V6(ip),
-                                        net,
-                                        static_service(service.service),
-                                    );
-                                }
+                // Leave time to load the datasets after the last retry
+                let fetcher = fetcher.with_deadline(
+                    Instant::now() + Duration::from_millis(CLOUD_PROVIDER_TIMEOUT * 9 / 10),
+                );
+                let mut report = UpdateReport::new();
+                if let Some(dir) = &snapshot_dir {
+                    // After a restart the new loads are compared with the last snapshot
+                    if registry.is_empty() {
+                        if let Err(err) = restore_cloud(dir, registry).await {
+                            // There is no snapshot before the first update
+                            if !err.is_not_found() {
+                                report.add_warning(task_error("Cannot restore the snapshot", err));
                             }
                         }
                     }
-                    Err(_) => {}
-                };
-                match get_azure_ips().await {
-                    Ok(azure_ranges) => {
-                        for service in azure_ranges.values {
-                            for prefix in service.properties.address_prefixes {
-                                match parse_ip4_network(&prefix) {
-                                    Some((ip, net)) => {
-                                        if !&service.properties.region.is_empty() {
-                                            cloud_provider.insert(
-                                                SiemIp::V4(ip),
-                                                net,
-                                                azure::static_region(&service.properties.region),
-                                            );
-                                        }
-                                        if !&service.properties.system_service.is_empty() {
-                                            cloud_service.insert(
-                                                SiemIp::V4(ip),
-                                                net,
-                                                azure::static_service(
-                                                    &service.properties.system_service,
-                                                ),
-                                            );
-                                        }
-                                    }
-                                    None => match parse_ip6_network(&prefix) {
-                                        Some((ip, net)) => {
-                                            if !&service.properties.region.is_empty() {
-                                                cloud_provider.insert(
-                                                    SiemIp::V6(ip),
-                                                    net,
-                                                    azure::static_region(
-                                                        &service.properties.region,
-                                                    ),
-                                                );
-                                            }
-                                            if !&service.properties.system_service.is_empty() {
-                                                cloud_service.insert(
-                                                    SiemIp::V6(ip),
-                                                    net,
-                                                    azure::static_service(
-                                                        &service.properties.system_service,
-                                                    ),
-                                                );
-                                            }
-                                        }
-                                        None => {}
-                                    },
-                                }
-                            }
-                        }
+                }
+                if aws_disabled {
+                    registry.remove("aws");
+                    report.add("AWS", SourceStatus::Skipped("disabled".to_string()));
+                } else {
+                    let res = match load_aws(&fetcher, &aws_url, mode).await {
+                        Ok(prefixes) => registry.replace("aws", prefixes, &thresholds),
+                        Err(err) => Err(err),
+                    };
+                    report.add_result("AWS", res);
+                }
+                if azure_disabled {
+                    registry.remove("azure");
+                    report.add("Azure", SourceStatus::Skipped("disabled".to_string()));
+                } else {
+                    let res = match load_azure(&fetcher, &azure_url, mode).await {
+                        Ok(prefixes) => registry.replace("azure", prefixes, &thresholds),
+                        Err(err) => Err(err),
+                    };
+                    report.add_result("Azure", res);
+                }
+                // The live datasets are replaced at once, keeping the last good copy of the failed sources
+                let loaded = report.loaded();
+                if loaded {
+                    cloud_provider.update(registry.build_provider());
+                    cloud_service.update(registry.build_service());
+                }
+                let mut data = report.into_result("IpCloudService and IpCloudProvider");
+                if let (true, Some(dir)) = (loaded, &snapshot_dir) {
+                    if let Err(err) = write_cloud_snapshot(dir, registry).await {
+                        data = data.map(|v| {
+                            format!("{}. {}", v, task_error("Cannot write the snapshot", err))
+                        });
                     }
-                    Err(_) => {}
-                };
-
+                }
                 SiemTaskResult {
-                    data: Some(Ok(format!("Correctly updated IpCloudService and IpCloudProvider"))),
+                    data: Some(data),
                     id: task.id,
                 }
             }))
         },
     )
 }
+
+pub async fn load_aws<T: FeedFetcher>(
+    fetcher: &T,
+    url: &str,
+    mode: PrefixMode,
+) -> Result<SourcePrefixes, TempErr> {
+    let (aws_ranges, origin) = get_aws_ips(fetcher, url).await?;
+    let mut loaded = SourcePrefixes {
+        cached: origin == FetchOrigin::Cached,
+        ..Default::default()
+    };
+    let prefixes = aws_ranges
+        .prefixes
+        .into_iter()
+        .map(|v| (v.ip_prefix, v.region, v.service));
+    let ipv6_prefixes = aws_ranges
+        .ipv6_prefixes
+        .into_iter()
+        .map(|v| (v.ipv6_prefix, v.region, v.service));
+    for (prefix, region, service) in prefixes.chain(ipv6_prefixes) {
+        let (ip, net) = match loaded.parse(&prefix, mode) {
+            Some(v) => v,
+            None => continue,
+        };
+        if !region.is_empty() {
+            loaded.provider.push((ip, net, static_region(region)));
+        }
+        if service != "AMAZON" && !service.is_empty() {
+            loaded.service.push((ip, net, static_service(service)));
+        }
+    }
+    Ok(loaded)
+}
+
+pub async fn load_azure<T: FeedFetcher>(
+    fetcher: &T,
+    url: &str,
+    mode: PrefixMode,
+) -> Result<SourcePrefixes, TempErr> {
+    let (azure_ranges, origin) = get_azure_ips(fetcher, url).await?;
+    let mut loaded = SourcePrefixes {
+        cached: origin == FetchOrigin::Cached,
+        ..Default::default()
+    };
+    for service in azure_ranges.values {
+        for prefix in service.properties.address_prefixes {
+            let (ip, net) = match loaded.parse(&prefix, mode) {
+                Some(v) => v,
+                None => continue,
+            };
+            if !service.properties.region.is_empty() {
+                loaded
+                    .provider
+                    .push((ip, net, azure::static_region(&service.properties.region)));
+            }
+            if !service.properties.system_service.is_empty() {
+                loaded.service.push((
+                    ip,
+                    net,
+                    azure::static_service(&service.properties.system_service),
+                ));
+            }
+        }
+    }
+    Ok(loaded)
+}
+
+#[cfg(test)]
+mod tests {
+    use usiem::prelude::{ip_net::IpNetDataset, SiemIp};
+
+    use super::*;
+    use crate::{fetcher::FileFetcher, test_dir::TestDir};
+
+    fn get(dataset: &IpNetDataset, ip: &str) -> Option<String> {
+        dataset
+            .get(&SiemIp::from_ip_str(ip).unwrap())
+            .map(|v| v.to_string())
+    }
+
+    #[tokio::test]
+    async fn should_load_cloud_provider_fixtures() {
+        let fetcher = FileFetcher::new(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures"));
+        let registry = CloudRegistry::default();
+        let thresholds = SanityThresholds {
+            min_prefixes: 1,
+            ..Default::default()
+        };
+        let aws = load_aws(&fetcher, DEFAULT_AWS_IP_RANGES_URL, PrefixMode::Strict)
+            .await
+            .unwrap();
+        assert_eq!(
+            5,
+            registry.replace("aws", aws, &thresholds).unwrap().prefixes
+        );
+        let azure = load_azure(&fetcher, DEFAULT_AZURE_SERVICE_TAGS_URL, PrefixMode::Strict)
+            .await
+            .unwrap();
+        // The invalid prefix is counted and ignored
+        let changes = registry.replace("azure", azure, &thresholds).unwrap();
+        assert_eq!((4, 1), (changes.prefixes, changes.rejected));
+        let providers = registry.build_provider();
+        let services = registry.build_service();
+
+        assert_eq!(
+            Some("AWS-us-east-1"),
+            get(&providers, "18.209.1.1").as_deref()
+        );
+        assert_eq!(Some("EC2"), get(&services, "18.209.1.1").as_deref());
+        assert_eq!(
+            Some("AWS-us-east-1"),
+            get(&providers, "2600:1f18::1").as_deref()
+        );
+        assert_eq!(Some("S3"), get(&services, "3.5.141.1").as_deref());
+        // The generic AMAZON service is not a service name
+        assert_eq!(
+            Some("AWS-us-west-2"),
+            get(&providers, "52.94.77.1").as_deref()
+        );
+        assert_eq!(None, get(&services, "52.94.77.1"));
+
+        assert_eq!(
+            Some("Azure-westeurope"),
+            get(&providers, "13.69.1.1").as_deref()
+        );
+        assert_eq!(
+            Some("Azure-westeurope"),
+            get(&providers, "2603:1020:200::1").as_deref()
+        );
+        assert_eq!(
+            Some("AzureStorage"),
+            get(&services, "20.38.109.1").as_deref()
+        );
+        assert_eq!(
+            Some("AzureStorage"),
+            get(&services, "2603:1020:205::1").as_deref()
+        );
+        assert_eq!(None, get(&providers, "8.8.8.8"));
+    }
+
+    #[tokio::test]
+    async fn should_fail_without_feeds() {
+        let dir = TestDir::new("missing_fixtures");
+        let fetcher = FileFetcher::new(dir.join("missing"));
+        assert!(
+            load_aws(&fetcher, DEFAULT_AWS_IP_RANGES_URL, PrefixMode::Lenient)
+                .await
+                .is_err()
+        );
+    }
+}
@@ -0,0 +1,355 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    sync::{Mutex, OnceLock},
+};
+
+use usiem::{
+    prelude::{ip_net::IpNetDataset, text_map::TextMapSynDataset, SiemIp},
+    utilities::types::LogString,
+};
+
+use crate::{
+    common::network_range,
+    err::{TempErr, TempResult},
+    network::{parse_network, PrefixMode},
+};
+
+use super::report::PrefixChanges;
+
+/// Configuration key with the minimum number of prefixes a source must have to be accepted. 10 by default.
+pub const FEED_MIN_PREFIXES: &str = "FEED_MIN_PREFIXES";
+/// Configuration key with the maximum drop, in percent, of the prefixes of a source compared with the previous load. 50 by default.
+pub const FEED_MAX_DROP_PERCENT: &str = "FEED_MAX_DROP_PERCENT";
+
+/// Configuration key with the validation of the feed networks. `strict` rejects the networks with host bits set
+/// and `lenient`, the default, loads them with the host bits cleared.
+pub const FEED_PREFIX_MODE: &str = "FEED_PREFIX_MODE";
+
+/// Mode configured in `FEED_PREFIX_MODE`
+pub fn prefix_mode(config: Option<&TextMapSynDataset>) -> PrefixMode {
+    config
+        .and_then(|v| v.get(&LogString::Borrowed(FEED_PREFIX_MODE)))
+        .and_then(|v| PrefixMode::from_name(v))
+        .unwrap_or_default()
+}
+
+/// Ranges of one cloud source, loaded before touching the live datasets
+#[derive(Clone, Debug, Default)]
+pub struct SourcePrefixes {
+    /// Number of prefixes in the feed
+    pub prefixes: usize,
+    /// Number of prefixes of the feed that were not valid networks
+    pub rejected: usize,
+    /// Values for the IpCloudProvider dataset
+    pub provider: Vec<(SiemIp, u8, LogString)>,
+    /// Values for the IpCloudService dataset
+    pub service: Vec<(SiemIp, u8, LogString)>,
+    /// The feed could not be downloaded and its last good copy was used
+    pub cached: bool,
+}
+
+impl SourcePrefixes {
+    /// Parses a prefix of the feed. Invalid prefixes are counted as rejected.
+    pub fn parse(&mut self, prefix: &str, mode: PrefixMode) -> Option<(SiemIp, u8)> {
+        match parse_network(prefix, mode) {
+            Ok(network) => {
+                self.prefixes += 1;
+                Some(network)
+            }
+            Err(_) => {
+                self.rejected += 1;
+                None
+            }
+        }
+    }
+
+    /// Distinct networks of the source, masked as in IpNetDataset
+    fn networks(&self) -> BTreeSet<(bool, u128, u8)> {
+        self.provider
+            .iter()
+            .chain(self.service.iter())
+            .map(|(ip, net, _)| match ip {
+                SiemIp::V4(ip) => (false, network_range(*ip as u128, *net, 32).0, *net),
+                SiemIp::V6(ip) => (true, network_range(*ip, *net, 128).0, *net),
+            })
+            .collect()
+    }
+
+    /// Networks added and removed compared with the previous load
+    pub fn changes(&self, previous: Option<&SourcePrefixes>) -> PrefixChanges {
+        let networks = self.networks();
+        let previous = previous.map(|v| v.networks()).unwrap_or_default();
+        PrefixChanges {
+            prefixes: self.prefixes,
+            added: networks.difference(&previous).count(),
+            removed: previous.difference(&networks).count(),
+            rejected: self.rejected,
+            cached: self.cached,
+        }
+    }
+}
+
+/// Checks that a new load is not a broken or truncated feed
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SanityThresholds {
+    pub min_prefixes: usize,
+    pub max_drop_percent: usize,
+}
+
+impl Default for SanityThresholds {
+    fn default() -> Self {
+        Self {
+            min_prefixes: 10,
+            max_drop_percent: 50,
+        }
+    }
+}
+
+impl SanityThresholds {
+    pub fn from_config(config: Option<&TextMapSynDataset>) -> Self {
+        let defaults = Self::default();
+        let value = |key: &'static str| {
+            config
+                .and_then(|v| v.get(&LogString::Borrowed(key)))
+                .and_then(|v| v.trim().parse::<usize>().ok())
+        };
+        Self {
+            min_prefixes: value(FEED_MIN_PREFIXES).unwrap_or(defaults.min_prefixes),
+            max_drop_percent: value(FEED_MAX_DROP_PERCENT)
+                .unwrap_or(defaults.max_drop_percent)
+                .min(100),
+        }
+    }
+
+    pub fn check(&self, loaded: usize, previous: Option<usize>) -> TempResult<()> {
+        if loaded < self.min_prefixes {
+            return Err(TempErr::invalid(format!(
+                "only {} prefixes, the minimum is {}",
+                loaded, self.min_prefixes
+            )));
+        }
+        if let Some(previous) = previous {
+            if loaded * 100 < previous * (100 - self.max_drop_percent) {
+                return Err(TempErr::invalid(format!(
+                    "{} prefixes after {} in the previous load, the drop is above {}%",
+                    loaded, previous, self.max_drop_percent
+                )));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Last accepted prefixes of each cloud source.
+///
+/// IpCloudService is filled by both the CloudProvider and CloudService tasks, so each rebuild
+/// includes the sources of the other task instead of replacing them.
+#[derive(Debug, Default)]
+pub struct CloudRegistry {
+    sources: Mutex<BTreeMap<String, SourcePrefixes>>,
+}
+
+impl CloudRegistry {
+    /// Registry shared by the tasks of this process
+    pub fn global() -> &'static CloudRegistry {
+        static REGISTRY: OnceLock<CloudRegistry> = OnceLock::new();
+        REGISTRY.get_or_init(CloudRegistry::default)
+    }
+
+    /// Stores the prefixes of a source if they pass the thresholds. Otherwise the previous ones are kept.
+    /// The withdrawn networks disappear from the datasets built afterwards.
+    pub fn replace(
+        &self,
+        source: &'static str,
+        prefixes: SourcePrefixes,
+        thresholds: &SanityThresholds,
+    ) -> TempResult<PrefixChanges> {
+        let mut sources = self.lock();
+        let previous = sources.get(source);
+        thresholds.check(prefixes.prefixes, previous.map(|v| v.prefixes))?;
+        let changes = prefixes.changes(previous);
+        sources.insert(source.to_string(), prefixes);
+        Ok(changes)
+    }
+
+    /// Forgets a source, used when it is disabled
+    pub fn remove(&self, source: &str) {
+        self.lock().remove(source);
+    }
+
+    pub fn prefixes(&self, source: &str) -> Option<usize> {
+        self.lock().get(source).map(|v| v.prefixes)
+    }
+
+    /// Copy of the prefixes of every source, used to write the snapshots
+    pub fn export(&self) -> BTreeMap<String, SourcePrefixes> {
+        self.lock().clone()
+    }
+
+    /// Loads the prefixes of a snapshot. Sources already loaded by a task are not replaced.
+    pub fn restore(&self, sources: BTreeMap<String, SourcePrefixes>) {
+        let mut current = self.lock();
+        for (source, prefixes) in sources {
+            current.entry(source).or_insert(prefixes);
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.lock().is_empty()
+    }
+
+    pub fn build_provider(&self) -> IpNetDataset {
+        self.build(|v| &v.provider)
+    }
+
+    pub fn build_service(&self) -> IpNetDataset {
+        self.build(|v| &v.service)
+    }
+
+    fn build(&self, values: fn(&SourcePrefixes) -> &Vec<(SiemIp, u8, LogString)>) -> IpNetDataset {
+        let mut dataset = IpNetDataset::new();
+        for source in self.lock().values() {
+            for (ip, net, value) in values(source) {
+                dataset.insert(*ip, *net, value.clone());
+            }
+        }
+        dataset
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, BTreeMap<String, SourcePrefixes>> {
+        // The map is always left in a consistent state
+        self.sources
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn source(prefixes: usize, service: &'static str) -> SourcePrefixes {
+        let mut source = SourcePrefixes::default();
+        for i in 0..prefixes {
+            let ip = SiemIp::V4(0x0a00_0000 + ((i as u32) << 8));
+            source.prefixes += 1;
+            source.service.push((ip, 24, LogString::Borrowed(service)));
+        }
+        source
+    }
+
+    fn get(dataset: &IpNetDataset, ip: &str) -> Option<String> {
+        dataset
+            .get(&SiemIp::from_ip_str(ip).unwrap())
+            .map(|v| v.to_string())
+    }
+
+    #[test]
+    fn should_keep_previous_prefixes_when_invalid() {
+        let registry = CloudRegistry::default();
+        let thresholds = SanityThresholds {
+            min_prefixes: 2,
+            max_drop_percent: 50,
+        };
+        assert_eq!(
+            PrefixChanges {
+                prefixes: 10,
+                added: 10,
+                removed: 0,
+                rejected: 0,
+                cached: false,
+            },
+            registry
+                .replace("aws", source(10, "EC2"), &thresholds)
+                .unwrap()
+        );
+        let o365 = SourcePrefixes {
+            prefixes: 2,
+            rejected: 0,
+            cached: false,
+            provider: Vec::new(),
+            service: vec![
+                (
+                    SiemIp::from_ip_str("40.97.0.0").unwrap(),
+                    16,
+                    LogString::Borrowed("Exchange"),
+                ),
+                (
+                    SiemIp::from_ip_str("2603:1006::").unwrap(),
+                    32,
+                    LogString::Borrowed("Exchange"),
+                ),
+            ],
+        };
+        registry.replace("o365", o365, &thresholds).unwrap();
+
+        // Sharp drop
+        let err = registry
+            .replace("aws", source(4, "S3"), &thresholds)
+            .unwrap_err();
+        assert_eq!(
+            "4 prefixes after 10 in the previous load, the drop is above 50%",
+            err.to_string()
+        );
+        // Below the minimum
+        assert!(registry
+            .replace("aws", source(1, "S3"), &thresholds)
+            .is_err());
+        assert_eq!(Some(10), registry.prefixes("aws"));
+
+        let services = registry.build_service();
+        assert_eq!(Some("EC2"), get(&services, "10.0.9.1").as_deref());
+        assert_eq!(Some("Exchange"), get(&services, "40.97.1.1").as_deref());
+        assert_eq!(Some("Exchange"), get(&services, "2603:1006::1").as_deref());
+
+        // Stale ranges are removed with the next valid load
+        let changes = registry
+            .replace("aws", source(5, "S3"), &thresholds)
+            .unwrap();
+        assert_eq!(5, changes.removed);
+        assert_eq!(0, changes.added);
+        let services = registry.build_service();
+        assert_eq!(Some("S3"), get(&services, "10.0.4.1").as_deref());
+        assert_eq!(None, get(&services, "10.0.9.1"));
+        assert_eq!(Some("Exchange"), get(&services, "40.97.1.1").as_deref());
+        registry.remove("o365");
+        assert_eq!(None, get(&registry.build_service(), "40.97.1.1"));
+    }
+
+    #[test]
+    fn should_count_rejected_prefixes() {
+        let prefixes = [
+            "10.1.2.3/8",
+            "10.0.0.0/33",
+            "::ffff:192.168.0.0/112",
+            "192.168.1.1",
+            "fe80::1%eth0/64",
+        ];
+        let mut strict = SourcePrefixes::default();
+        let parsed: Vec<_> = prefixes
+            .iter()
+            .filter_map(|v| strict.parse(v, PrefixMode::Strict))
+            .collect();
+        assert_eq!(
+            vec![(SiemIp::V4(0xc0a8_0000), 16), (SiemIp::V4(0xc0a8_0101), 32)],
+            parsed
+        );
+        assert_eq!((2, 3), (strict.prefixes, strict.rejected));
+
+        let mut lenient = SourcePrefixes::default();
+        let parsed: Vec<_> = prefixes
+            .iter()
+            .filter_map(|v| lenient.parse(v, PrefixMode::Lenient))
+            .collect();
+        assert_eq!(
+            vec![
+                (SiemIp::V4(0x0a00_0000), 8),
+                (SiemIp::V4(0xc0a8_0000), 16),
+                (SiemIp::V4(0xc0a8_0101), 32),
+                (SiemIp::V6(0xfe80 << 112), 64)
+            ],
+            parsed
+        );
+        assert_eq!((4, 1), (lenient.prefixes, lenient.rejected));
+    }
+}
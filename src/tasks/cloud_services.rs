@@ -1,51 +1,129 @@
+use std::{
+    collections::BTreeMap,
+    time::{Duration, Instant},
+};
+
 use usiem::{
     prelude::{
         ip_net::IpNetSynDataset,
         task::{SiemTaskData, SiemTaskResult, TaskDefinition, TaskFireMode},
-        SiemDatasetType, SiemError, SiemIp,
-    },
-    utilities::{
-        ip_utils::{ipv4_from_str, ipv6_from_str},
-        types::LogString,
+        text_map::TextMapSynDataset,
+        SiemDatasetType, SiemError,
     },
+    utilities::types::LogString,
 };
 
 use crate::{
-    err::TempErr,
+    err::{task_error, TempErr},
+    fetcher::{feed_url, CachedFetcher, FeedFetcher, FetchOrigin},
+    network::PrefixMode,
     o365,
+    snapshot::{restore_cloud, snapshot_dir, write_cloud_snapshot},
+};
+
+use super::{
+    cloud_registry::{prefix_mode, CloudRegistry, SanityThresholds, SourcePrefixes},
+    report::{is_source_disabled, SourceStatus, UpdateReport},
 };
 
+const CLOUD_SERVICE_TIMEOUT: u64 = 600_000;
+
 pub fn cloud_service_definition() -> TaskDefinition {
     TaskDefinition::new(
-        SiemTaskData::UPDATE_GEOIP,
+        SiemTaskData::OTHER(LogString::Borrowed("UPDATE_CLOUD_SERVICE"), BTreeMap::new()),
         LogString::Borrowed("CloudService"),
         LogString::Borrowed("Update cloud service dataset with O365 IPs"),
         usiem::prelude::UserRole::Administrator,
         TaskFireMode::Repetitive(86400000),
-        600_000,
+        CLOUD_SERVICE_TIMEOUT,
         |task, datasets| {
             let cloud_service: IpNetSynDataset =
                 match datasets.get(&SiemDatasetType::IpCloudService) {
                     Some(v) => match v.clone().try_into() {
                         Ok(v) => v,
-                        Err(_) => {
-                            return Err(SiemError::Task(format!(
-                                "IpCloudService dataset is not supported by this SIEM implementation"
-                            )))
-                        }
+                        Err(_) => return Err(SiemError::Task(
+                            "IpCloudService dataset is not supported by this SIEM implementation"
+                                .to_string(),
+                        )),
                     },
                     None => {
-                        return Err(SiemError::Task(format!(
+                        return Err(SiemError::Task(
                             "IpCloudService is not supported by this SIEM implementation"
-                        )))
+                                .to_string(),
+                        ))
                     }
                 };
+            let config: Option<TextMapSynDataset> = datasets
+                .get(&SiemDatasetType::Configuration)
+                .and_then(|v| v.clone().try_into().ok());
+            let secrets: Option<TextMapSynDataset> = datasets
+                .get(&SiemDatasetType::Secrets(LogString::Borrowed(
+                    "CloudService",
+                )))
+                .and_then(|v| v.clone().try_into().ok());
+            let fetcher = match CachedFetcher::from_datasets(config.as_ref(), secrets.as_ref()) {
+                Ok(v) => v,
+                Err(err) => {
+                    return Err(SiemError::Task(task_error(
+                        "Cannot create the HTTP client, check FEED_PROXY and FEED_CA_BUNDLE",
+                        err,
+                    )))
+                }
+            };
 
-            Ok(Box::pin(async move {
-                let _o365_res = process_o365(&cloud_service);
+            let o365_disabled = is_source_disabled(config.as_ref(), "o365");
+            let url = feed_url(
+                config.as_ref(),
+                o365::O365_ENDPOINTS_URL,
+                o365::DEFAULT_O365_ENDPOINTS_URL,
+            );
 
+            let registry = CloudRegistry::global();
+            let thresholds = SanityThresholds::from_config(config.as_ref());
+            let mode = prefix_mode(config.as_ref());
+            let snapshot_dir = snapshot_dir(config.as_ref());
+
+            Ok(Box::pin(async move {
+                let fetcher = fetcher.with_deadline(
+                    Instant::now() + Duration::from_millis(CLOUD_SERVICE_TIMEOUT * 9 / 10),
+                );
+                let mut report = UpdateReport::new();
+                if let Some(dir) = &snapshot_dir {
+                    // After a restart the new loads are compared with the last snapshot
+                    if registry.is_empty() {
+                        if let Err(err) = restore_cloud(dir, registry).await {
+                            // There is no snapshot before the first update
+                            if !err.is_not_found() {
+                                report.add_warning(task_error("Cannot restore the snapshot", err));
+                            }
+                        }
+                    }
+                }
+                if o365_disabled {
+                    registry.remove("o365");
+                    report.add("O365", SourceStatus::Skipped("disabled".to_string()));
+                } else {
+                    let res = match load_o365(&fetcher, &url, mode).await {
+                        Ok(prefixes) => registry.replace("o365", prefixes, &thresholds),
+                        Err(err) => Err(err),
+                    };
+                    report.add_result("O365", res);
+                }
+                // Also contains the services of the CloudProvider task
+                let loaded = report.loaded();
+                if loaded {
+                    cloud_service.update(registry.build_service());
+                }
+                let mut data = report.into_result("IpCloudService");
+                if let (true, Some(dir)) = (loaded, &snapshot_dir) {
+                    if let Err(err) = write_cloud_snapshot(dir, registry).await {
+                        data = data.map(|v| {
+                            format!("{}. {}", v, task_error("Cannot write the snapshot", err))
+                        });
+                    }
+                }
                 SiemTaskResult {
-                    data: Some(Ok(format!("Correctly updated CloudService"))),
+                    data: Some(data),
                     id: task.id,
                 }
             }))
@@ -53,39 +131,70 @@ pub fn cloud_service_definition() -> TaskDefinition {
     )
 }
 
-pub async fn process_o365(dataset: &IpNetSynDataset) -> Result<(), TempErr> {
-    let res = o365::get_office365_ip().await;
-    let res = match res {
-        Ok(v) => v,
-        Err(err) => return Err(err),
+pub async fn load_o365<T: FeedFetcher>(
+    fetcher: &T,
+    url: &str,
+    mode: PrefixMode,
+) -> Result<SourcePrefixes, TempErr> {
+    let (res, origin) = o365::get_office365_ip(fetcher, url).await?;
+    let mut loaded = SourcePrefixes {
+        cached: origin == FetchOrigin::Cached,
+        ..Default::default()
     };
     for service in res {
         for text in &service.ips {
-            let (ip, net) = match text.rfind("/") {
-                Some(pos) => {
-                    let ip = &text[..pos];
-                    let net = &text[pos + 1..];
-                    let net = match net.parse::<u8>() {
-                        Ok(net) => net,
-                        Err(_) => continue,
-                    };
-                    let ip = if ip.contains(":") {
-                        match ipv4_from_str(ip) {
-                            Ok(v) => SiemIp::V4(v),
-                            Err(_) => continue,
-                        }
-                    } else {
-                        match ipv6_from_str(ip) {
-                            Ok(v) => SiemIp::V6(v),
-                            Err(_) => continue,
-                        }
-                    };
-                    (ip, net)
-                }
+            let (ip, net) = match loaded.parse(text, mode) {
+                Some(v) => v,
                 None => continue,
             };
-            dataset.insert(ip, net, o365::static_service(&service.service_area));
+            loaded
+                .service
+                .push((ip, net, o365::static_service(&service.service_area)));
         }
     }
-    Ok(())
+    Ok(loaded)
+}
+
+#[cfg(test)]
+mod tests {
+    use usiem::prelude::SiemIp;
+
+    use super::*;
+    use crate::test_dir::TestDir;
+
+    #[tokio::test]
+    async fn should_load_o365_fixtures() {
+        // Mirrors can also be local files
+        let dir = TestDir::new("o365");
+        let fetcher = CachedFetcher::new(dir.join("cache"));
+        let url = format!(
+            "file://{}/tests/fixtures/o365-endpoints.json",
+            env!("CARGO_MANIFEST_DIR")
+        );
+        let registry = CloudRegistry::default();
+        let thresholds = SanityThresholds {
+            min_prefixes: 1,
+            ..Default::default()
+        };
+        let loaded = load_o365(&fetcher, &url, PrefixMode::Strict).await.unwrap();
+        assert_eq!(
+            5,
+            registry
+                .replace("o365", loaded, &thresholds)
+                .unwrap()
+                .prefixes
+        );
+        let dataset = registry.build_service();
+        let get = |ip: &str| {
+            dataset
+                .get(&SiemIp::from_ip_str(ip).unwrap())
+                .map(|v| v.to_string())
+        };
+        assert_eq!(Some("Exchange"), get("40.97.1.1").as_deref());
+        assert_eq!(Some("Exchange"), get("13.107.6.153").as_deref());
+        assert_eq!(Some("Exchange"), get("2603:1006::1").as_deref());
+        assert_eq!(Some("O365 Common"), get("20.20.33.1").as_deref());
+        assert_eq!(Some("O365 Common"), get("2603:1037::1").as_deref());
+        assert_eq!(None, get("8.8.8.8"));
+    }
 }
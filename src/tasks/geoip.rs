@@ -1,6 +1,16 @@
+#[cfg(not(feature = "slow_geoip"))]
+use std::{
+    path::Path,
+    sync::atomic::{AtomicBool, Ordering},
+};
+use std::{
+    path::PathBuf,
+    time::{Duration, Instant},
+};
+
 use usiem::{
     prelude::{
-        geo_ip::GeoIpSynDataset,
+        geo_ip::{GeoIpDataset, GeoIpSynDataset},
         task::{SiemTaskData, SiemTaskResult, TaskDefinition, TaskFireMode},
         text_map::TextMapSynDataset,
         SiemDatasetType, SiemError,
@@ -8,173 +18,482 @@ use usiem::{
     utilities::types::LogString,
 };
 
-use crate::maxmind::{
-    download_maxmind_geo_litle2_asn, download_maxmind_geo_litle2_city,
-    download_maxmind_geo_litle2_country, extract_zip_db, join_path_files,
-    process_maxmind_geo_lite2_csv,
+use super::cloud_registry::prefix_mode;
+#[cfg(not(feature = "slow_geoip"))]
+use crate::snapshot::{restore_geoip, snapshot_dir, write_geoip_snapshot};
+use crate::{
+    dbip::{
+        download_dbip_city_lite, extract_gz_db, process_dbip_city_lite_csv, DBIP_BASE_URL,
+        DEFAULT_DBIP_BASE_URL,
+    },
+    err::task_error,
+    fetcher::{feed_url, CachedFetcher, FeedFetcher},
+    ip2location::IP2LOCATION_DB11_FILE,
+    ip2location::{
+        download_ip2location_lite_db11, process_ip2location_db11_csv, DEFAULT_IP2LOCATION_BASE_URL,
+        IP2LOCATION_BASE_URL, IP2LOCATION_DB11_IPV6_FILE,
+    },
+    maxmind::{
+        download_maxmind_geo_litle2_asn, download_maxmind_geo_litle2_city,
+        download_maxmind_geo_litle2_country, extract_zip_db, import_local_db, join_path_files,
+        process_maxmind_geo_lite2_csv, MaxMindSource, DEFAULT_MAXMIND_BASE_URL, MAXMIND_BASE_URL,
+    },
+    network::{LoadedEntries, PrefixMode},
 };
 
+/// Configuration key with the GeoIP provider: `maxmind` (default), `ip2location` or `dbip`
+pub const GEOIP_PROVIDER: &str = "GEOIP_PROVIDER";
+/// Configuration key with comma separated local paths of the database files. When present nothing is downloaded.
+/// MaxMind and IP2Location accept folders with the CSV files, `.zip` and `.tar.gz` files. DB-IP accepts `.csv` or `.csv.gz` files,
+/// all of them are loaded.
+pub const GEOIP_LOCAL_PATH: &str = "GEOIP_LOCAL_PATH";
+
+const GEOIP_TIMEOUT: u64 = 600_000;
+
+/// The snapshot is only restored by the first run of the task
+#[cfg(not(feature = "slow_geoip"))]
+static SNAPSHOT_RESTORED: AtomicBool = AtomicBool::new(false);
+
+/// Source of the GeoIP database. The credentials are only needed to download the databases.
+pub enum GeoIpProvider {
+    /// MaxMind GeoLite2. Downloads need the `MAXMIND_API` secret
+    MaxMind {
+        source: Option<MaxMindSource>,
+        language: String,
+    },
+    /// IP2Location LITE DB11. Downloads need the `IP2LOCATION_TOKEN` secret
+    Ip2Location {
+        base_url: String,
+        token: Option<String>,
+    },
+    /// DB-IP IP to City Lite. Does not need an account
+    DbIp { base_url: String },
+}
+
 pub fn geoip_definition() -> TaskDefinition {
     TaskDefinition::new(
         SiemTaskData::UPDATE_GEOIP,
         LogString::Borrowed("UpdateGeoIP"),
-        LogString::Borrowed("Update geo ip dataset with maxmind database"),
+        LogString::Borrowed("Update geo ip dataset with maxmind, ip2location or db-ip databases"),
         usiem::prelude::UserRole::Administrator,
         TaskFireMode::Repetitive(86_400_000),
-        600_000,
+        GEOIP_TIMEOUT,
         |task, datasets| {
-            let secrets: TextMapSynDataset = match datasets.get(&SiemDatasetType::Secrets(
-                LogString::Borrowed("UpdateGeoIP"),
-            )) {
+            let geoip: GeoIpSynDataset = match datasets.get(&SiemDatasetType::GeoIp) {
                 Some(v) => match v.clone().try_into() {
                     Ok(v) => v,
                     Err(_) => {
-                        return Err(SiemError::Task(format!(
-                            "Secrets dataset is not supported by this SIEM implementation"
-                        )))
+                        return Err(SiemError::Task(
+                            "GeoIpDataset is not supported by this SIEM implementation".to_string(),
+                        ))
                     }
                 },
                 None => {
-                    return Err(SiemError::Task(format!(
-                        "Secrets dataset is not supported by this SIEM implementation"
+                    return Err(SiemError::Task(
+                        "GeoIpDataset is not supported by this SIEM implementation".to_string(),
+                    ))
+                }
+            };
+            let config: Option<TextMapSynDataset> = datasets
+                .get(&SiemDatasetType::Configuration)
+                .and_then(|v| v.clone().try_into().ok());
+            let local_paths: Vec<PathBuf> = config
+                .as_ref()
+                .and_then(|v| v.get(&LogString::Borrowed(GEOIP_LOCAL_PATH)))
+                .map(|v| {
+                    v.split(',')
+                        .map(|v| v.trim())
+                        .filter(|v| !v.is_empty())
+                        .map(PathBuf::from)
+                        .collect()
+                })
+                .unwrap_or_default();
+            let download = local_paths.is_empty();
+            let mode = prefix_mode(config.as_ref());
+            let provider_name = config
+                .as_ref()
+                .and_then(|v| v.get(&LogString::Borrowed(GEOIP_PROVIDER)))
+                .map(|v| v.trim().to_lowercase())
+                .unwrap_or_else(|| "maxmind".to_string());
+            let secrets: Option<TextMapSynDataset> = datasets
+                .get(&SiemDatasetType::Secrets(LogString::Borrowed(
+                    "UpdateGeoIP",
+                )))
+                .and_then(|v| v.clone().try_into().ok());
+            let fetcher = match CachedFetcher::from_datasets(config.as_ref(), secrets.as_ref()) {
+                Ok(v) => v,
+                Err(err) => {
+                    return Err(SiemError::Task(task_error(
+                        "Cannot create the HTTP client, check FEED_PROXY and FEED_CA_BUNDLE",
+                        err,
                     )))
                 }
             };
-            let geoip: GeoIpSynDataset = match datasets.get(&SiemDatasetType::GeoIp) {
-                Some(v) => match v.clone().try_into() {
-                    Ok(v) => v,
-                    Err(_) => {
-                        return Err(SiemError::Task(format!(
-                            "GeoIpDataset is not supported by this SIEM implementation"
-                        )))
+            let secret = |name: &'static str| {
+                secrets
+                    .as_ref()
+                    .and_then(|v| v.get(&LogString::Borrowed(name)))
+                    .map(|v| v.to_string())
+            };
+            let provider = match &provider_name[..] {
+                "maxmind" => {
+                    if download && secrets.is_none() {
+                        return Err(SiemError::Task(
+                            "Secrets dataset is not supported by this SIEM implementation"
+                                .to_string(),
+                        ));
+                    }
+                    let base_url =
+                        feed_url(config.as_ref(), MAXMIND_BASE_URL, DEFAULT_MAXMIND_BASE_URL);
+                    // Local mirrors do not need a license key
+                    let api_key = match secret("MAXMIND_API") {
+                        Some(v) => Some(v),
+                        None if base_url.starts_with("file://") => Some(String::new()),
+                        None => None,
+                    };
+                    if download && api_key.is_none() {
+                        return Err(SiemError::Task(
+                            "Cannot find MAXMIND_API secret".to_string(),
+                        ));
                     }
+                    let source = api_key.map(|license_key| MaxMindSource {
+                        base_url,
+                        account_id: secret("MAXMIND_ACCOUNT_ID"),
+                        license_key,
+                    });
+                    let language = secret("MAXMIND_LANGUAGE")
+                        .map(|v| v.to_lowercase())
+                        .unwrap_or_else(|| "en".to_string());
+                    GeoIpProvider::MaxMind { source, language }
+                }
+                "ip2location" => {
+                    let base_url = feed_url(
+                        config.as_ref(),
+                        IP2LOCATION_BASE_URL,
+                        DEFAULT_IP2LOCATION_BASE_URL,
+                    );
+                    let token = match secret("IP2LOCATION_TOKEN") {
+                        Some(v) => Some(v),
+                        None if base_url.starts_with("file://") => Some(String::new()),
+                        None => None,
+                    };
+                    if download && token.is_none() {
+                        return Err(SiemError::Task(
+                            "Cannot find IP2LOCATION_TOKEN secret".to_string(),
+                        ));
+                    }
+                    GeoIpProvider::Ip2Location { base_url, token }
+                }
+                "dbip" | "db-ip" => GeoIpProvider::DbIp {
+                    base_url: feed_url(config.as_ref(), DBIP_BASE_URL, DEFAULT_DBIP_BASE_URL),
                 },
-                None => {
+                _ => {
                     return Err(SiemError::Task(format!(
-                        "GeoIpDataset is not supported by this SIEM implementation"
+                        "Unknown GEOIP_PROVIDER {}",
+                        provider_name
                     )))
                 }
             };
-            let maxmind_api = match secrets.get(&LogString::Borrowed("MAXMIND_API")) {
-                Some(v) => v.to_string(),
-                None => return Err(SiemError::Task(format!("Cannot find MAXMIND_API secret"))),
-            };
-            let language = match secrets.get(&LogString::Borrowed("MAXMIND_LANGUAGE")) {
-                Some(v) => v.to_lowercase(),
-                None => format!("en")
-            };
 
+            #[cfg(not(feature = "slow_geoip"))]
+            let snapshot_dir = snapshot_dir(config.as_ref());
             #[cfg(feature = "slow_geoip")]
             let slow_location = {
-                let config: TextMapSynDataset = match datasets.get(&SiemDatasetType::Configuration) {
-                    Some(v) => match v.clone().try_into() {
-                        Ok(v) => v,
-                        Err(_) => {
-                            return Err(SiemError::Task(format!(
-                                "Configuration is not supported by this SIEM implementation"
-                            )))
-                        }
-                    },
+                let config = match config {
+                    Some(v) => v,
                     None => {
-                        return Err(SiemError::Task(format!(
+                        return Err(SiemError::Task(
                             "Configuration is not supported by this SIEM implementation"
-                        )))
+                                .to_string(),
+                        ))
                     }
                 };
                 let location = match config.get(&LogString::Borrowed("SLOW_GEO_IP")) {
                     Some(v) => v.to_string(),
-                    None => return Err(SiemError::Task(format!(
-                        "SLOW_GEO_IP configuration is not setted, cannot update dataset"
-                    )))
+                    None => {
+                        return Err(SiemError::Task(
+                            "SLOW_GEO_IP configuration is not setted, cannot update dataset"
+                                .to_string(),
+                        ))
+                    }
                 };
                 location
             };
 
             Ok(Box::pin(async move {
-                let asn_path = match download_maxmind_geo_litle2_asn(&maxmind_api).await {
-                    Ok(v) => v,
-                    Err(_err) => {
-                        return SiemTaskResult {
-                            data: Some(Err(format!("Cannot download maxmind ASN"))),
-                            id: task.id,
-                        }
-                    }
-                };
-                let city_path = match download_maxmind_geo_litle2_city(&maxmind_api).await {
-                    Ok(v) => v,
-                    Err(_err) => {
-                        return SiemTaskResult {
-                            data: Some(Err(format!("Cannot download maxmind City"))),
-                            id: task.id,
-                        }
-                    }
-                };
-
-                let country_path = match download_maxmind_geo_litle2_country(&maxmind_api).await {
-                    Ok(v) => v,
-                    Err(_err) => {
-                        return SiemTaskResult {
-                            data: Some(Err(format!("Cannot download maxmind Country"))),
-                            id: task.id,
-                        }
-                    }
-                };
-                let city_path = match extract_zip_db(&city_path).await {
-                    Ok(v) => v,
-                    Err(_) => {
-                        return SiemTaskResult {
-                            data: Some(Err(format!("Cannot extract city database"))),
-                            id: task.id,
-                        }
-                    }
-                };
-                let country_path = match extract_zip_db(&country_path).await {
-                    Ok(v) => v,
-                    Err(_) => {
-                        return SiemTaskResult {
-                            data: Some(Err(format!("Cannot extract country database"))),
-                            id: task.id,
-                        }
-                    }
-                };
-                let asn_path = match extract_zip_db(&asn_path).await {
-                    Ok(v) => v,
-                    Err(_) => {
-                        return SiemTaskResult {
-                            data: Some(Err(format!("Cannot extract ASN database"))),
-                            id: task.id,
-                        }
-                    }
-                };
-                let new_path = match join_path_files(vec![city_path, country_path, asn_path]).await
-                {
-                    Ok(v) => v,
-                    Err(_) => {
-                        return SiemTaskResult {
-                            data: Some(Err(format!("Cannot copy database files"))),
-                            id: task.id,
+                // Leave time to process the databases after the last retry
+                let fetcher = fetcher
+                    .with_deadline(Instant::now() + Duration::from_millis(GEOIP_TIMEOUT / 2));
+                #[allow(unused_mut)]
+                let mut restore_error: Option<String> = None;
+                // After a restart enrichment uses the last snapshot until the databases are processed
+                #[cfg(not(feature = "slow_geoip"))]
+                if let Some(dir) = &snapshot_dir {
+                    if !SNAPSHOT_RESTORED.swap(true, Ordering::Relaxed) {
+                        match restore_geoip_snapshot(dir).await {
+                            Ok(Some(dataset)) => geoip.full_update(dataset),
+                            Ok(None) => {}
+                            Err(err) => restore_error = Some(err),
                         }
                     }
+                }
+                let with_restore_error = |message: String| match &restore_error {
+                    Some(err) => format!("{}. {}", message, err),
+                    None => message,
                 };
                 #[cfg(not(feature = "slow_geoip"))]
-                let tsk = process_maxmind_geo_lite2_csv(new_path, true, &language);
+                let tsk = update_geoip(&fetcher, provider, &local_paths, mode);
                 #[cfg(feature = "slow_geoip")]
-                let tsk = process_maxmind_geo_lite2_csv(new_path, true, &language, &slow_location);
-                let dataset = match tsk.await {
+                let tsk = update_geoip(&fetcher, provider, &local_paths, mode, &slow_location);
+                let (dataset, entries) = match tsk.await {
                     Ok(v) => v,
-                    Err(_) => {
+                    Err(err) => {
                         return SiemTaskResult {
-                            data: Some(Err(format!("Cannot process database files"))),
+                            data: Some(Err(with_restore_error(err))),
                             id: task.id,
                         }
                     }
                 };
+                #[allow(unused_mut)]
+                let mut message = with_restore_error(geoip_message(&entries));
+                // The slow dataset is already stored on disk
+                #[cfg(not(feature = "slow_geoip"))]
+                if let Some(dir) = &snapshot_dir {
+                    if let Err(err) = write_geoip_snapshot(dir, &dataset).await {
+                        message = format!(
+                            "{}. {}",
+                            message,
+                            task_error("Cannot write the snapshot", err)
+                        );
+                    }
+                }
                 geoip.full_update(dataset);
                 SiemTaskResult {
-                    data: Some(Ok(format!("Correctly updated GeoIpDatabase"))),
+                    data: Some(Ok(message)),
                     id: task.id,
                 }
             }))
         },
     )
 }
+
+fn geoip_message(entries: &LoadedEntries) -> String {
+    if entries.rejected == 0 {
+        return "Correctly updated GeoIpDatabase".to_string();
+    }
+    format!(
+        "Correctly updated GeoIpDatabase, {} entries rejected ({})",
+        entries.rejected,
+        entries.samples_text()
+    )
+}
+
+/// Reads the snapshot written by a previous run of the task, `None` if there is no snapshot yet
+#[cfg(not(feature = "slow_geoip"))]
+pub async fn restore_geoip_snapshot(dir: &Path) -> Result<Option<GeoIpDataset>, String> {
+    match restore_geoip(dir).await {
+        Ok(dataset) => Ok(Some(dataset)),
+        Err(err) if err.is_not_found() => Ok(None),
+        Err(err) => Err(task_error("Cannot restore the snapshot", err)),
+    }
+}
+
+/// Builds the GeoIp dataset from the files in `local_paths`, or downloads them from the provider when empty.
+/// The networks and ranges of the databases are validated with `mode`.
+pub async fn update_geoip<T: FeedFetcher>(
+    fetcher: &T,
+    provider: GeoIpProvider,
+    local_paths: &[PathBuf],
+    mode: PrefixMode,
+    #[cfg(feature = "slow_geoip")] slow_location: &str,
+) -> Result<(GeoIpDataset, LoadedEntries), String> {
+    match provider {
+        GeoIpProvider::MaxMind { source, language } => {
+            let new_path = match (local_paths.is_empty(), source) {
+                (false, _) => import_local_db(local_paths)
+                    .await
+                    .map_err(|err| task_error("Cannot import local maxmind database", err))?,
+                (true, Some(source)) => download_maxmind(fetcher, &source).await?,
+                (true, None) => return Err("Cannot find MAXMIND_API secret".to_string()),
+            };
+            #[cfg(not(feature = "slow_geoip"))]
+            let tsk = process_maxmind_geo_lite2_csv(&new_path, true, &language, mode);
+            #[cfg(feature = "slow_geoip")]
+            let tsk =
+                process_maxmind_geo_lite2_csv(&new_path, true, &language, mode, slow_location);
+            tsk.await
+                .map_err(|err| task_error("Cannot process database files", err))
+        }
+        GeoIpProvider::Ip2Location { base_url, token } => {
+            let path = match (local_paths.is_empty(), token) {
+                (false, _) => import_local_db(local_paths)
+                    .await
+                    .map_err(|err| task_error("Cannot import local IP2Location database", err))?,
+                (true, Some(token)) => {
+                    let zip_path = download_ip2location_lite_db11(fetcher, &base_url, &token, true)
+                        .await
+                        .map_err(|err| task_error("Cannot download IP2Location DB11", err))?;
+                    extract_zip_db(&zip_path)
+                        .await
+                        .map_err(|err| task_error("Cannot extract IP2Location database", err))?
+                }
+                (true, None) => return Err("Cannot find IP2LOCATION_TOKEN secret".to_string()),
+            };
+            // The IPv6 edition also contains the IPv4 ranges
+            let mut csv_path = path.join(IP2LOCATION_DB11_IPV6_FILE);
+            if !csv_path.exists() {
+                csv_path = path.join(IP2LOCATION_DB11_FILE);
+            }
+            #[cfg(not(feature = "slow_geoip"))]
+            let mut dataset = GeoIpDataset::new();
+            #[cfg(feature = "slow_geoip")]
+            let mut dataset = GeoIpDataset::new(slow_location);
+            let entries = process_ip2location_db11_csv(csv_path, mode, &mut dataset)
+                .await
+                .map_err(|err| task_error("Cannot process IP2Location database", err))?;
+            Ok((dataset, entries))
+        }
+        GeoIpProvider::DbIp { base_url } => {
+            let gz_paths = if local_paths.is_empty() {
+                vec![download_dbip_city_lite(fetcher, &base_url)
+                    .await
+                    .map_err(|err| task_error("Cannot download DB-IP City Lite", err))?]
+            } else {
+                local_paths.to_vec()
+            };
+            #[cfg(not(feature = "slow_geoip"))]
+            let mut dataset = GeoIpDataset::new();
+            #[cfg(feature = "slow_geoip")]
+            let mut dataset = GeoIpDataset::new(slow_location);
+            // Every file is loaded into the same dataset, like the IPv4 and IPv6 files of the other providers
+            let mut entries = LoadedEntries::default();
+            for gz_path in gz_paths {
+                let path = if gz_path.extension().map(|v| v == "gz").unwrap_or(false) {
+                    extract_gz_db(&gz_path)
+                        .await
+                        .map_err(|err| task_error("Cannot extract DB-IP database", err))?
+                } else {
+                    gz_path
+                };
+                let loaded = process_dbip_city_lite_csv(&path, mode, &mut dataset)
+                    .await
+                    .map_err(|err| task_error("Cannot process DB-IP database", err))?;
+                entries.merge(loaded);
+            }
+            Ok((dataset, entries))
+        }
+    }
+}
+
+/// Downloads and extracts the City, Country and ASN databases into the same folder
+async fn download_maxmind<T: FeedFetcher>(
+    fetcher: &T,
+    source: &MaxMindSource,
+) -> Result<std::path::PathBuf, String> {
+    let asn_path = download_maxmind_geo_litle2_asn(fetcher, source)
+        .await
+        .map_err(|err| task_error("Cannot download maxmind ASN", err))?;
+    let city_path = download_maxmind_geo_litle2_city(fetcher, source)
+        .await
+        .map_err(|err| task_error("Cannot download maxmind City", err))?;
+    let country_path = download_maxmind_geo_litle2_country(fetcher, source)
+        .await
+        .map_err(|err| task_error("Cannot download maxmind Country", err))?;
+    let city_path = extract_zip_db(&city_path)
+        .await
+        .map_err(|err| task_error("Cannot extract city database", err))?;
+    let country_path = extract_zip_db(&country_path)
+        .await
+        .map_err(|err| task_error("Cannot extract country database", err))?;
+    let asn_path = extract_zip_db(&asn_path)
+        .await
+        .map_err(|err| task_error("Cannot extract ASN database", err))?;
+    join_path_files(vec![city_path, country_path, asn_path])
+        .await
+        .map_err(|err| task_error("Cannot copy database files", err))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::*;
+    use crate::network::InvalidNetwork;
+
+    #[test]
+    fn should_report_rejected_entries() {
+        let mut entries = LoadedEntries {
+            loaded: 10,
+            ..Default::default()
+        };
+        assert_eq!("Correctly updated GeoIpDatabase", geoip_message(&entries));
+        let path = Path::new("GeoLite2-City-Blocks-IPv4.csv");
+        entries.reject(path, 3, InvalidNetwork::HostBits("1.0.1.7/24".into()));
+        assert_eq!(
+            "Correctly updated GeoIpDatabase, 1 entries rejected (GeoLite2-City-Blocks-IPv4.csv:3: host bits set in 1.0.1.7/24)",
+            geoip_message(&entries)
+        );
+        for line in 4..10 {
+            entries.reject(path, line, InvalidNetwork::Address("x".into()));
+        }
+        assert!(geoip_message(&entries).starts_with("Correctly updated GeoIpDatabase, 7 entries rejected (GeoLite2-City-Blocks-IPv4.csv:3: host bits set in 1.0.1.7/24, "));
+        assert!(geoip_message(&entries).ends_with(":7: invalid address in x, ...)"));
+    }
+
+    #[cfg(not(feature = "slow_geoip"))]
+    #[tokio::test]
+    async fn should_restore_the_built_dataset() {
+        use usiem::prelude::SiemIp;
+
+        use crate::{fetcher::FileFetcher, snapshot::write_geoip_snapshot, test_dir::TestDir};
+
+        let dir = TestDir::new("geoip_task");
+        assert_eq!(
+            Ok(None),
+            restore_geoip_snapshot(&dir).await.map(|v| v.map(|_| ()))
+        );
+        // Every DB-IP file is loaded
+        let ipv4 = dir.join("dbip-city-lite-ipv4.csv");
+        tokio::fs::write(
+            &ipv4,
+            "1.0.4.0,1.0.7.255,OC,AU,Victoria,Melbourne,-37.814,144.963\n",
+        )
+        .await
+        .unwrap();
+        let ipv6 = dir.join("dbip-city-lite-ipv6.csv");
+        tokio::fs::write(
+            &ipv6,
+            "2001:200::,2001:200:ffff:ffff:ffff:ffff:ffff:ffff,AS,JP,Tokyo,Tokyo,35.6895,139.692\n",
+        )
+        .await
+        .unwrap();
+        let provider = GeoIpProvider::DbIp {
+            base_url: DEFAULT_DBIP_BASE_URL.to_string(),
+        };
+        let (dataset, entries) = update_geoip(
+            &FileFetcher::new(dir.to_path_buf()),
+            provider,
+            &[ipv4, ipv6],
+            PrefixMode::Strict,
+        )
+        .await
+        .unwrap();
+        assert_eq!(2, entries.loaded);
+        write_geoip_snapshot(&dir, &dataset).await.unwrap();
+
+        let restored = restore_geoip_snapshot(&dir).await.unwrap().unwrap();
+        let city = |ip: &str| {
+            restored
+                .get(&SiemIp::from_ip_str(ip).unwrap())
+                .map(|v| v.city.to_string())
+        };
+        assert_eq!(Some("Melbourne".to_string()), city("1.0.5.1"));
+        assert_eq!(Some("Tokyo".to_string()), city("2001:200::1"));
+        assert_eq!(None, city("1.0.8.1"));
+
+        tokio::fs::write(dir.join("GeoIp.snapshot"), b"invalid")
+            .await
+            .unwrap();
+        assert!(restore_geoip_snapshot(&dir).await.is_err());
+    }
+}
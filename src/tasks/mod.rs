@@ -1,3 +1,7 @@
+pub mod asn_type;
 pub mod cloud_provider;
+pub mod cloud_registry;
 pub mod cloud_services;
 pub mod geoip;
+pub mod network_zone;
+pub mod report;
@@ -0,0 +1,158 @@
+use std::collections::BTreeMap;
+
+use usiem::{
+    prelude::{
+        ip_net::IpNetSynDataset,
+        task::{SiemTaskData, SiemTaskResult, TaskDefinition, TaskFireMode},
+        text_map::TextMapSynDataset,
+        try_to_custom_map_ip_net, SiemDatasetType, SiemError,
+    },
+    utilities::types::LogString,
+};
+
+use crate::{
+    err::task_error,
+    network::LoadedEntries,
+    network_zone::{
+        build_network_zone_datasets, read_network_zones_file, NETWORK_SITE_DATASET,
+        NETWORK_VLAN_DATASET, NETWORK_ZONE_DATASET,
+    },
+};
+
+/// Configuration key with the path of the CSV or YAML file with the network zones
+pub const NETWORK_ZONES_FILE: &str = "NETWORK_ZONES_FILE";
+
+fn network_zone_message(entries: &LoadedEntries) -> String {
+    if entries.rejected == 0 {
+        return format!(
+            "Correctly updated NetworkZone with {} networks",
+            entries.loaded
+        );
+    }
+    format!(
+        "Correctly updated NetworkZone with {} networks, {} rejected ({})",
+        entries.loaded,
+        entries.rejected,
+        entries.samples_text()
+    )
+}
+
+pub fn network_zone_definition() -> TaskDefinition {
+    TaskDefinition::new(
+        SiemTaskData::OTHER(LogString::Borrowed("UPDATE_NETWORK_ZONE"), BTreeMap::new()),
+        LogString::Borrowed("NetworkZone"),
+        LogString::Borrowed("Update network zone, VLAN and site datasets from a CSV or YAML file"),
+        usiem::prelude::UserRole::Administrator,
+        TaskFireMode::Repetitive(3_600_000),
+        60_000,
+        |task, datasets| {
+            let config: TextMapSynDataset = match datasets.get(&SiemDatasetType::Configuration) {
+                Some(v) => match v.clone().try_into() {
+                    Ok(v) => v,
+                    Err(_) => {
+                        return Err(SiemError::Task(
+                            "Configuration is not supported by this SIEM implementation"
+                                .to_string(),
+                        ))
+                    }
+                },
+                None => {
+                    return Err(SiemError::Task(
+                        "Configuration is not supported by this SIEM implementation".to_string(),
+                    ))
+                }
+            };
+            let path = match config.get(&LogString::Borrowed(NETWORK_ZONES_FILE)) {
+                Some(v) => v.to_string(),
+                None => {
+                    return Err(SiemError::Task(
+                        "NETWORK_ZONES_FILE configuration is not setted, cannot update dataset"
+                            .to_string(),
+                    ))
+                }
+            };
+            let zone: IpNetSynDataset = match datasets.get(&SiemDatasetType::CustomMapIpNet(
+                LogString::Borrowed(NETWORK_ZONE_DATASET),
+            )) {
+                Some(v) => match try_to_custom_map_ip_net(v.clone(), NETWORK_ZONE_DATASET) {
+                    Ok(v) => v,
+                    Err(_) => {
+                        return Err(SiemError::Task(
+                            "NetworkZone dataset is not supported by this SIEM implementation"
+                                .to_string(),
+                        ))
+                    }
+                },
+                None => {
+                    return Err(SiemError::Task(
+                        "NetworkZone dataset is not supported by this SIEM implementation"
+                            .to_string(),
+                    ))
+                }
+            };
+            let vlan: Option<IpNetSynDataset> = datasets
+                .get(&SiemDatasetType::CustomMapIpNet(LogString::Borrowed(
+                    NETWORK_VLAN_DATASET,
+                )))
+                .and_then(|v| try_to_custom_map_ip_net(v.clone(), NETWORK_VLAN_DATASET).ok());
+            let site: Option<IpNetSynDataset> = datasets
+                .get(&SiemDatasetType::CustomMapIpNet(LogString::Borrowed(
+                    NETWORK_SITE_DATASET,
+                )))
+                .and_then(|v| try_to_custom_map_ip_net(v.clone(), NETWORK_SITE_DATASET).ok());
+
+            Ok(Box::pin(async move {
+                let records = match read_network_zones_file(&path).await {
+                    Ok(v) => v,
+                    Err(err) => {
+                        return SiemTaskResult {
+                            data: Some(Err(task_error("Cannot read network zone file", err))),
+                            id: task.id,
+                        }
+                    }
+                };
+                let built = build_network_zone_datasets(&records);
+                let message = network_zone_message(&built.entries);
+                zone.update(built.zone);
+                if let Some(vlan) = vlan {
+                    vlan.update(built.vlan);
+                }
+                if let Some(site) = site {
+                    site.update(built.site);
+                }
+                SiemTaskResult {
+                    data: Some(Ok(message)),
+                    id: task.id,
+                }
+            }))
+        },
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::network_zone::NetworkZoneRecord;
+
+    #[test]
+    fn should_report_loaded_and_rejected_networks() {
+        let records: Vec<NetworkZoneRecord> = ["10.0.0.0/8", "10.1.0.0/16", "10.0.0.0/33"]
+            .iter()
+            .map(|network| NetworkZoneRecord {
+                network: network.to_string(),
+                zone: "internal".to_string(),
+                ..Default::default()
+            })
+            .collect();
+        let built = build_network_zone_datasets(&records[..2]);
+        assert_eq!(
+            "Correctly updated NetworkZone with 2 networks",
+            network_zone_message(&built.entries)
+        );
+        let built = build_network_zone_datasets(&records);
+        assert_eq!(
+            "Correctly updated NetworkZone with 2 networks, 1 rejected (invalid prefix length in 10.0.0.0/33)",
+            network_zone_message(&built.entries)
+        );
+    }
+}
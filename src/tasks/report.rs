@@ -0,0 +1,190 @@
+use std::fmt::Display;
+
+use usiem::{prelude::text_map::TextMapSynDataset, utilities::types::LogString};
+
+use crate::err::{error_chain, TempErr};
+
+/// Configuration key with a comma separated list of sources that are not downloaded: `aws`, `azure` or `o365`
+pub const FEED_DISABLED_SOURCES: &str = "FEED_DISABLED_SOURCES";
+
+/// Prefixes of a source and the networks that changed since its previous load
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PrefixChanges {
+    pub prefixes: usize,
+    pub added: usize,
+    pub removed: usize,
+    /// Prefixes of the feed that were not valid networks
+    pub rejected: usize,
+    /// The feed could not be downloaded and its last good copy was used
+    pub cached: bool,
+}
+
+/// Result of loading one source of an update task
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SourceStatus {
+    /// The source was loaded and replaced the previous prefixes
+    Loaded(PrefixChanges),
+    /// The feed could not be downloaded and the source was loaded from its last good copy, which may be stale
+    Cached(PrefixChanges),
+    /// The source was not downloaded, with the reason
+    Skipped(String),
+    /// The source could not be loaded, with the reason
+    Failed(String),
+}
+
+/// Per-source summary used as the result of the update tasks
+#[derive(Clone, Debug, Default)]
+pub struct UpdateReport {
+    sources: Vec<(&'static str, SourceStatus)>,
+    /// Problems of the task that do not belong to a source
+    warnings: Vec<String>,
+}
+
+impl UpdateReport {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add(&mut self, source: &'static str, status: SourceStatus) {
+        self.sources.push((source, status));
+    }
+
+    pub fn add_result(&mut self, source: &'static str, result: Result<PrefixChanges, TempErr>) {
+        let status = match result {
+            Ok(loaded) if loaded.cached => SourceStatus::Cached(loaded),
+            Ok(loaded) => SourceStatus::Loaded(loaded),
+            Err(err) => SourceStatus::Failed(error_chain(&err)),
+        };
+        self.add(source, status);
+    }
+
+    /// Adds a problem that does not prevent loading the sources
+    pub fn add_warning(&mut self, warning: String) {
+        self.warnings.push(warning);
+    }
+
+    pub fn sources(&self) -> &[(&'static str, SourceStatus)] {
+        &self.sources
+    }
+
+    pub fn warnings(&self) -> &[String] {
+        &self.warnings
+    }
+
+    /// At least one source was loaded
+    pub fn loaded(&self) -> bool {
+        self.sources
+            .iter()
+            .any(|(_, status)| matches!(status, SourceStatus::Loaded(_) | SourceStatus::Cached(_)))
+    }
+
+    /// The task fails when no source was loaded and at least one of them failed
+    pub fn into_result(self, datasets: &str) -> Result<String, String> {
+        let loaded = self.loaded();
+        let failed = self
+            .sources
+            .iter()
+            .any(|(_, status)| matches!(status, SourceStatus::Failed(_)));
+        if !loaded && failed {
+            Err(format!("Cannot update {}. {}", datasets, self))
+        } else {
+            Ok(format!("Updated {}. {}", datasets, self))
+        }
+    }
+}
+
+impl Display for UpdateReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (pos, (source, status)) in self.sources.iter().enumerate() {
+            if pos > 0 {
+                write!(f, ", ")?;
+            }
+            match status {
+                SourceStatus::Loaded(changes) | SourceStatus::Cached(changes) => {
+                    let origin = match status {
+                        SourceStatus::Cached(_) => " from the cached copy",
+                        _ => "",
+                    };
+                    write!(
+                        f,
+                        "{}: loaded {} prefixes{} ({} added, {} removed",
+                        source, changes.prefixes, origin, changes.added, changes.removed
+                    )?;
+                    if changes.rejected > 0 {
+                        write!(f, ", {} rejected", changes.rejected)?;
+                    }
+                    write!(f, ")")?
+                }
+                SourceStatus::Skipped(reason) => write!(f, "{}: skipped ({})", source, reason)?,
+                SourceStatus::Failed(reason) => write!(f, "{}: failed ({})", source, reason)?,
+            }
+        }
+        for (pos, warning) in self.warnings.iter().enumerate() {
+            if pos > 0 || !self.sources.is_empty() {
+                write!(f, ". ")?;
+            }
+            write!(f, "{}", warning)?;
+        }
+        Ok(())
+    }
+}
+
+/// Checks if the source is listed in `FEED_DISABLED_SOURCES`
+pub fn is_source_disabled(config: Option<&TextMapSynDataset>, source: &str) -> bool {
+    config
+        .and_then(|v| v.get(&LogString::Borrowed(FEED_DISABLED_SOURCES)))
+        .map(|v| v.split(',').any(|v| v.trim().eq_ignore_ascii_case(source)))
+        .unwrap_or(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_summarize_sources() {
+        let mut report = UpdateReport::new();
+        report.add_result(
+            "AWS",
+            Ok(PrefixChanges {
+                prefixes: 120,
+                added: 4,
+                removed: 2,
+                rejected: 1,
+                cached: false,
+            }),
+        );
+        report.add_result("Azure", Err(TempErr::HttpStatus(503)));
+        report.add("O365", SourceStatus::Skipped("disabled".to_string()));
+        assert_eq!(
+            Ok("Updated IpCloudProvider. AWS: loaded 120 prefixes (4 added, 2 removed, 1 rejected), Azure: failed (unexpected HTTP status 503), O365: skipped (disabled)".to_string()),
+            report.into_result("IpCloudProvider")
+        );
+
+        let mut report = UpdateReport::new();
+        report.add_warning("Cannot restore the snapshot: invalid".to_string());
+        report.add_result(
+            "O365",
+            Ok(PrefixChanges {
+                prefixes: 5,
+                cached: true,
+                ..Default::default()
+            }),
+        );
+        assert_eq!(
+            Ok("Updated IpCloudService. O365: loaded 5 prefixes from the cached copy (0 added, 0 removed). Cannot restore the snapshot: invalid".to_string()),
+            report.into_result("IpCloudService")
+        );
+
+        let mut report = UpdateReport::new();
+        report.add_result("AWS", Err(TempErr::Timeout));
+        report.add("Azure", SourceStatus::Skipped("disabled".to_string()));
+        assert_eq!(
+            Err(
+                "Cannot update IpCloudProvider. AWS: failed (timeout waiting for the server), Azure: skipped (disabled)"
+                    .to_string()
+            ),
+            report.into_result("IpCloudProvider")
+        );
+    }
+}